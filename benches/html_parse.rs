@@ -0,0 +1,56 @@
+// Run with: cargo bench --features bench --bench html_parse
+extern crate browser_engine;
+use browser_engine::html_parse::HtmlParser;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+
+// Roughly 2 MB of nested markup, text, attributes and comments.
+fn large_document() -> String {
+    let mut html = String::from("<html><body>");
+    let mut i = 0;
+    while html.len() < 2 * 1024 * 1024 {
+        html.push_str(&format!(
+            "<div class=\"row item-{}\" id=\"r{}\">\n  <!-- row {} -->\n  <p>Lorem ipsum dolor sit amet, \
+             <b>consectetur</b> adipiscing   elit, sed do eiusmod tempor.</p>\n  \
+             <a href=\"/page/{}\" title='link {}'>read more</a>\n</div>\n",
+            i % 7,
+            i,
+            i,
+            i,
+            i
+        ));
+        i += 1;
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn main() {
+    let html = large_document();
+
+    // Warm up once so the first measured run does not pay for page faults.
+    black_box(HtmlParser::new(&html).parse_nodes());
+
+    let mut total = Duration::ZERO;
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        black_box(HtmlParser::new(black_box(&html)).parse_nodes());
+        let elapsed = start.elapsed();
+        total += elapsed;
+        best = best.min(elapsed);
+    }
+
+    let mb = html.len() as f64 / (1024.0 * 1024.0);
+    let mean = total / ITERATIONS;
+    println!(
+        "html_parse: {:.2} MB, mean {:?}, best {:?}, {:.1} MB/s",
+        mb,
+        mean,
+        best,
+        mb / mean.as_secs_f64()
+    );
+}
@@ -0,0 +1,98 @@
+// Run with: cargo bench --features bench --bench style_index
+extern crate browser_engine;
+use browser_engine::css_parser::CssParser;
+use browser_engine::dom::{Node, NodeType};
+use browser_engine::html_parse::HtmlParser;
+use browser_engine::style::{matching_rules, StyleIndex};
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 10;
+const TAGS: [&str; 8] = ["div", "p", "span", "a", "ul", "li", "section", "h2"];
+
+// About 3000 rules, mostly class selectors like a utility framework, plus some ids and tags.
+fn large_stylesheet() -> String {
+    let mut css = String::new();
+    for i in 0..3000 {
+        let selector = match i % 10 {
+            0 => format!("#id-{}", i % 97),
+            1 | 2 => format!("{}.c-{}", TAGS[i % TAGS.len()], i % 500),
+            3 => TAGS[i % TAGS.len()].to_string(),
+            _ => format!(".c-{}, .alt-{}", i % 500, i % 300),
+        };
+        css.push_str(&format!(
+            "{} {{ margin-top: {}px; color: red; }}\n",
+            selector,
+            i % 20
+        ));
+    }
+    css
+}
+
+fn large_document() -> String {
+    let mut html = String::from("<html><body>");
+    for i in 0..2000 {
+        let tag = TAGS[i % TAGS.len()];
+        html.push_str(&format!(
+            "<{} id=\"id-{}\" class=\"c-{} c-{} alt-{}\"><span class=\"c-{}\">x</span></{}>",
+            tag,
+            i % 150,
+            i % 700,
+            (i * 7) % 500,
+            i % 400,
+            (i * 3) % 600,
+            tag
+        ));
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn match_all(
+    node: &Node,
+    stylesheet: &browser_engine::css::Stylesheet,
+    index: Option<&StyleIndex>,
+) -> usize {
+    let mut matched = 0;
+    if let NodeType::Element(ref e) = node.node_type {
+        matched += matching_rules(e, stylesheet, index).len();
+    }
+    for child in &node.children {
+        matched += match_all(child, stylesheet, index);
+    }
+    matched
+}
+
+fn time<F: FnMut() -> usize>(mut f: F) -> Duration {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let css = large_stylesheet();
+    let html = large_document();
+    let stylesheet = CssParser::new(&css).parse_stylesheet();
+    let nodes = HtmlParser::new(&html).parse_nodes();
+    let root = &nodes[0];
+    let index = StyleIndex::new(&stylesheet);
+
+    assert_eq!(
+        match_all(root, &stylesheet, None),
+        match_all(root, &stylesheet, Some(&index))
+    );
+
+    let brute_force = time(|| match_all(root, &stylesheet, None));
+    let indexed = time(|| match_all(root, &stylesheet, Some(&index)));
+    println!(
+        "style_index: {} rules, brute force {:?}, indexed {:?}, {:.1}x faster",
+        stylesheet.rules.len(),
+        brute_force,
+        indexed,
+        brute_force.as_secs_f64() / indexed.as_secs_f64()
+    );
+}
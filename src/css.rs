@@ -27,13 +27,19 @@ pub struct Declaration {
     pub property: String,
     pub value: Value,
 }
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone)]
 pub enum Value {
     Color(Color),
     Length(f32, Unit),
     Other(String),
+    // Quoted string without its quotes and with escapes resolved, `font-family: "Helvetica Neue"`.
+    String(String),
+    // Space separated components, `margin: 10px 20px`.
+    List(Vec<Value>),
+    // Comma separated items, `font-family: helvetica, arial`. An item with spaces is a List.
+    CommaList(Vec<Value>),
 }
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum Unit {
     Em,
     Ex,
@@ -65,6 +71,17 @@ impl Stylesheet {
     pub fn new(rules: Vec<Rule>) -> Stylesheet {
         Stylesheet { rules }
     }
+
+    // Appends the rules of other after the rules of self, keeping their order.
+    pub fn merge(&mut self, other: Stylesheet) {
+        self.rules.extend(other.rules);
+    }
+}
+
+impl FromIterator<Rule> for Stylesheet {
+    fn from_iter<I: IntoIterator<Item = Rule>>(iter: I) -> Self {
+        Stylesheet::new(iter.into_iter().collect())
+    }
 }
 
 impl Default for Stylesheet {
@@ -237,6 +254,32 @@ impl fmt::Debug for Value {
             Value::Color(ref c) => write!(f, "{:?}", c),
             Value::Length(l, _) => write!(f, "{:?}", l),
             Value::Other(ref o) => write!(f, "{:?}", o),
+            Value::String(ref s) => write!(f, "{:?}", s),
+            Value::List(ref l) => write_list(f, l, " "),
+            Value::CommaList(ref l) => write_list(f, l, ", "),
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, values: &[Value], separator: &str) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{:?}", value)?;
+    }
+    write!(f, "]")
+}
+
+impl Value {
+    /* The top level components of the value: the items of a list, or the value itself
+    for anything else, so `margin: 10px` and `margin: 10px 20px` can be read the same way.
+    */
+    pub fn components(&self) -> &[Value] {
+        match *self {
+            Value::List(ref l) | Value::CommaList(ref l) => l,
+            _ => std::slice::from_ref(self),
         }
     }
 }
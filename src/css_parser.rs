@@ -27,7 +27,10 @@ impl<'a> CssParser<'a> {
         stylesheet
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    /* Comma separated selectors up to the '{' of a rule. Also parses selector text on its own,
+    CssParser::new("p.note, #header").parse_selectors(), as used by StyledNode::select.
+    */
+    pub fn parse_selectors(&mut self) -> Vec<Selector> {
         let mut selectors = Vec::new();
 
         while self.chars.peek().map_or(false, |c| *c != '{') {
@@ -77,7 +80,8 @@ impl<'a> CssParser<'a> {
                 }
                 Some(&c) if c == '.' => {
                     self.chars.next();
-                    let class_name = self.parse_identifier();
+                    // Class names are case-sensitive, unlike tag names.
+                    let class_name = self.parse_name();
 
                     if class_name != String::from("") {
                         sselector.classes.push(class_name);
@@ -95,6 +99,11 @@ impl<'a> CssParser<'a> {
     }
 
     fn parse_identifier(&mut self) -> String {
+        self.parse_name().to_lowercase()
+    }
+
+    // An identifier as written, without lowercasing.
+    fn parse_name(&mut self) -> String {
         let mut ident = String::new();
         match self.chars.peek() {
             Some(&c) => {
@@ -104,7 +113,7 @@ impl<'a> CssParser<'a> {
             }
             None => {}
         }
-        ident.to_lowercase()
+        ident
     }
 
     fn parse_id(&mut self) -> Option<String> {
@@ -125,29 +134,50 @@ impl<'a> CssParser<'a> {
             self.chars.next();
             self.consume_while(char::is_whitespace);
 
-            let value = self
-                .consume_while(|x| x != ';' && x != '\n' && x != '}')
-                .to_lowercase();
+            let value = match self.parse_value() {
+                Some(value) => value,
+                None => {
+                    // An unterminated string drops the declaration, parsing goes on with the next line.
+                    self.consume_while(char::is_whitespace);
+                    continue;
+                }
+            };
+
+            let components = split_components(&value);
 
-            let value_enum = match property.as_ref() {
-                "background-color" | "border-color" | "color" => {
-                    Value::Color(translate_color(&value))
+            /* Single values and lists are typed the same way, component by component. The
+            property only matters when a single value is not a string, a length or a color:
+            color properties then fall back to translate_color and length properties to
+            translate_length, as before lists were parsed (`width: 10` is 10px).
+            */
+            let typed = if components.is_empty() {
+                Value::Other(value.clone())
+            } else {
+                translate_list(&components)
+            };
+            let value_enum = match (property.as_ref(), typed) {
+                ("background-color" | "border-color" | "color", Value::Other(_)) => {
+                    Value::Color(translate_color(value.trim()))
                 }
-                "margin-right"
-                | "margin-bottom"
-                | "margin-left"
-                | "margin-top"
-                | "padding-right"
-                | "padding-bottom"
-                | "padding-left"
-                | "padding-top"
-                | "border-right-width"
-                | "border-bottom-width"
-                | "border-left-width"
-                | "border-top-width"
-                | "height"
-                | "width" => translate_length(&value),
-                _ => Value::Other(value),
+                (
+                    "margin-right"
+                    | "margin-bottom"
+                    | "margin-left"
+                    | "margin-top"
+                    | "padding-right"
+                    | "padding-bottom"
+                    | "padding-left"
+                    | "padding-top"
+                    | "border-right-width"
+                    | "border-bottom-width"
+                    | "border-left-width"
+                    | "border-top-width"
+                    | "height"
+                    | "width"
+                    | "font-size",
+                    Value::Other(_),
+                ) => translate_length(value.trim()),
+                (_, typed) => typed,
             };
 
             let declaration = Declaration::new(property, value_enum);
@@ -167,6 +197,48 @@ impl<'a> CssParser<'a> {
         declarations
     }
 
+    /* Reads a declaration value up to ';', '}' or the end of the line. Text outside strings is
+    lowercased, strings ("..." or '...') are kept as written, quotes and escapes included, and
+    may contain ';' and '}'. Returns None for a string not closed before the end of the line,
+    with the rest of that line consumed.
+        font-family: "Helvetica Neue", Arial;  ->  "Helvetica Neue", arial
+        content: "a;b";                         ->  "a;b"
+        content: "open                          ->  None
+    */
+    fn parse_value(&mut self) -> Option<String> {
+        let mut value = String::new();
+
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ';' | '}' | '\n' => break,
+                '"' | '\'' => {
+                    value.push(c);
+                    self.chars.next();
+                    loop {
+                        match self.chars.next()? {
+                            '\n' => return None,
+                            '\\' => {
+                                value.push('\\');
+                                value.push(self.chars.next()?);
+                            }
+                            ch => {
+                                value.push(ch);
+                                if ch == c {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    value.extend(c.to_lowercase());
+                    self.chars.next();
+                }
+            }
+        }
+        Some(value)
+    }
+
     fn consume_while<F>(&mut self, condition: F) -> String
     where
         F: Fn(char) -> bool,
@@ -179,6 +251,157 @@ impl<'a> CssParser<'a> {
     }
 }
 
+/* Splits a declaration value into comma separated groups of space separated components.
+Commas and spaces inside parentheses or quotes do not split, so rgb(0, 0, 0) stays one component.
+    "helvetica, arial, sans-serif" -> [["helvetica"], ["arial"], ["sans-serif"]]
+    "\"helvetica neue\", arial"    -> [["\"helvetica neue\""], ["arial"]]
+    "10px 20px 0 5px"              -> [["10px", "20px", "0", "5px"]]
+    "opacity 0.3s ease, color 1s"  -> [["opacity", "0.3s", "ease"], ["color", "1s"]]
+*/
+fn split_components(value: &str) -> Vec<Vec<&str>> {
+    let mut groups = Vec::new();
+    let mut group = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in value.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '"' | '\'' => quote = Some(c),
+            _ => {}
+        }
+        let separator = depth <= 0 && (c == ',' || c.is_whitespace());
+        match start {
+            Some(s) if separator => {
+                group.push(&value[s..i]);
+                start = None;
+            }
+            None if !separator => start = Some(i),
+            _ => {}
+        }
+        if depth <= 0 && c == ',' {
+            groups.push(group);
+            group = Vec::new();
+        }
+    }
+    if let Some(s) = start {
+        group.push(&value[s..]);
+    }
+    groups.push(group);
+    groups.retain(|g| !g.is_empty());
+    groups
+}
+
+// Builds a Value::List per space separated group, inside a Value::CommaList if there are several groups.
+fn translate_list(groups: &[Vec<&str>]) -> Value {
+    let mut items: Vec<Value> = groups
+        .iter()
+        .map(|group| match group[..] {
+            [single] => translate_component(single),
+            _ => Value::List(group.iter().map(|c| translate_component(c)).collect()),
+        })
+        .collect();
+
+    if items.len() == 1 {
+        items.remove(0)
+    } else {
+        Value::CommaList(items)
+    }
+}
+
+// A single component of a multi-value declaration: a string, a length, a color or a keyword.
+fn translate_component(component: &str) -> Value {
+    if let Some(string) = parse_string(component) {
+        Value::String(string)
+    } else if let Some(length) = parse_length(component) {
+        length
+    } else if is_color(component) {
+        Value::Color(translate_color(component))
+    } else {
+        Value::Other(component.to_string())
+    }
+}
+
+/* The content of a quoted component ("..." or '...'), None if it is not one.
+A backslash escapes the next char (\" -> ", \\ -> \) and a backslash before a newline is dropped.
+*/
+fn parse_string(component: &str) -> Option<String> {
+    let quote = component
+        .chars()
+        .next()
+        .filter(|&c| c == '"' || c == '\'')?;
+    let inner = component[1..].strip_suffix(quote)?;
+
+    let mut string = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\n') | None => {}
+                Some(escaped) => string.push(escaped),
+            },
+            _ => string.push(c),
+        }
+    }
+    Some(string)
+}
+
+/* Like translate_length but strict: accepts a sign and a decimal part and returns None
+for anything that is not a number followed by a known length unit ("0.3s", "ease").
+A number without a unit is only a length when it is 0, "600" or "0.5" stay keywords.
+*/
+fn parse_length(component: &str) -> Option<Value> {
+    let unit_start = component
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map_or(component.len(), |(i, _)| i);
+    let number: f32 = component[..unit_start].parse().ok()?;
+
+    let unit = match &component[unit_start..] {
+        "em" => Unit::Em,
+        "ex" => Unit::Ex,
+        "ch" => Unit::Ch,
+        "rem" => Unit::Rem,
+        "vh" => Unit::Vh,
+        "vw" => Unit::Vw,
+        "vmin" => Unit::Vmin,
+        "vmax" => Unit::Vmax,
+        "px" => Unit::Px,
+        "" if number == 0.0 => Unit::Px,
+        "mm" => Unit::Mm,
+        "q" => Unit::Q,
+        "cm" => Unit::Cm,
+        "in" => Unit::In,
+        "pt" => Unit::Pt,
+        "pc" => Unit::Pc,
+        "%" => Unit::Pct,
+        _ => return None,
+    };
+    Some(Value::Length(number, unit))
+}
+
+// translate_color turns unknown names into black, so anything else that comes out black is not a color.
+fn is_color(component: &str) -> bool {
+    component.starts_with('#')
+        || component.starts_with("rgb")
+        || component.starts_with("hsl")
+        || component == "black"
+        || translate_color(component) != Color::new(0.0, 0.0, 0.0, 1.0)
+}
+
 fn translate_length(value: &str) -> Value {
     let mut num_str = String::new();
     let mut unit = String::new();
@@ -774,3 +997,220 @@ fn is_lower_letter(c: char) -> bool {
 fn is_non_ascii(c: char) -> bool {
     c >= '\u{0080}'
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::style::ComputedStyle;
+
+    // Value of the only declaration of `declaration`, parsed inside a rule.
+    fn value_of(declaration: &str) -> Value {
+        let mut sheet = CssParser::new(&format!("p {{ {} }}", declaration)).parse_stylesheet();
+        sheet.rules.remove(0).declarations.remove(0).value
+    }
+
+    fn other(keyword: &str) -> Value {
+        Value::Other(keyword.to_string())
+    }
+
+    fn px(n: f32) -> Value {
+        Value::Length(n, Unit::Px)
+    }
+
+    #[test]
+    fn multi_value_declarations() {
+        let cases = [
+            (
+                "font-family: Helvetica, Arial, sans-serif;",
+                Value::CommaList(vec![
+                    other("helvetica"),
+                    other("arial"),
+                    other("sans-serif"),
+                ]),
+            ),
+            (
+                "margin: 10px 20px 0 5px;",
+                Value::List(vec![px(10.0), px(20.0), px(0.0), px(5.0)]),
+            ),
+            (
+                "transition: opacity 0.3s ease;",
+                Value::List(vec![other("opacity"), other("0.3s"), other("ease")]),
+            ),
+            (
+                "transition: opacity 0.3s ease, color 1s;",
+                Value::CommaList(vec![
+                    Value::List(vec![other("opacity"), other("0.3s"), other("ease")]),
+                    Value::List(vec![other("color"), other("1s")]),
+                ]),
+            ),
+            ("margin: 0 auto;", Value::List(vec![px(0.0), other("auto")])),
+            (
+                "border: 1px solid red;",
+                Value::List(vec![
+                    px(1.0),
+                    other("solid"),
+                    Value::Color(Color::new(1.0, 0.0, 0.0, 1.0)),
+                ]),
+            ),
+            ("color: rgb(0, 0, 0);", Value::Color(Color::default())),
+            (
+                "background: rgb(0, 0, 0) none;",
+                Value::List(vec![Value::Color(Color::default()), other("none")]),
+            ),
+            (
+                "margin: -1.5em +2rem 50%;",
+                Value::List(vec![
+                    Value::Length(-1.5, Unit::Em),
+                    Value::Length(2.0, Unit::Rem),
+                    Value::Length(50.0, Unit::Pct),
+                ]),
+            ),
+        ];
+
+        for (declaration, expected) in cases {
+            assert_eq!(value_of(declaration), expected, "{}", declaration);
+        }
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn quoted_strings() {
+        let cases = [
+            (
+                r#"font-family: "Helvetica Neue", Arial, 'Times New Roman', serif;"#,
+                Value::CommaList(vec![
+                    string("Helvetica Neue"),
+                    other("arial"),
+                    string("Times New Roman"),
+                    other("serif"),
+                ]),
+            ),
+            (r#"content: "→ ";"#, string("→ ")),
+            (r#"content: "a\"b";"#, string("a\"b")),
+            (r#"content: 'it\'s';"#, string("it's")),
+            (r#"content: "it's";"#, string("it's")),
+            (r#"content: '"q"';"#, string("\"q\"")),
+            (r#"content: "a\\b";"#, string("a\\b")),
+            (r#"content: "";"#, string("")),
+            (r#"content: "a;b}c";"#, string("a;b}c")),
+            (
+                r#"quotes: "\"" '\'';"#,
+                Value::List(vec![string("\""), string("'")]),
+            ),
+            (
+                r#"content: "x, y" counter(n);"#,
+                Value::List(vec![string("x, y"), other("counter(n)")]),
+            ),
+        ];
+
+        for (declaration, expected) in cases {
+            assert_eq!(value_of(declaration), expected, "{}", declaration);
+        }
+    }
+
+    #[test]
+    fn unterminated_strings() {
+        let sheet = CssParser::new(
+            "p {\n  content: \"open;\n  width: 5px;\n}\n\
+             h1 { content: 'a\n  height: 3px; color: red; }\n\
+             em { width: 5px; content: 'end",
+        )
+        .parse_stylesheet();
+        let declarations: Vec<Vec<&str>> = sheet
+            .rules
+            .iter()
+            .map(|rule| {
+                rule.declarations
+                    .iter()
+                    .map(|d| d.property.as_str())
+                    .collect()
+            })
+            .collect();
+
+        // The string ends with its line and only its own declaration is dropped.
+        assert_eq!(
+            declarations,
+            [vec!["width"], vec!["height", "color"], vec!["width"]]
+        );
+        assert_eq!(sheet.rules[0].declarations[0].value, px(5.0));
+        assert_eq!(sheet.rules[1].declarations[0].value, px(3.0));
+        assert_eq!(sheet.rules[2].declarations[0].value, px(5.0));
+    }
+
+    #[test]
+    fn split_components_groups() {
+        assert_eq!(
+            split_components("helvetica, arial,sans-serif"),
+            [vec!["helvetica"], vec!["arial"], vec!["sans-serif"]]
+        );
+        assert_eq!(split_components(" 10px  20px "), [vec!["10px", "20px"]]);
+        assert_eq!(
+            split_components("rgb(0, 0, 0) hsl(1, 2%, 3%), x"),
+            [vec!["rgb(0, 0, 0)", "hsl(1, 2%, 3%)"], vec!["x"]]
+        );
+        assert!(split_components("").is_empty());
+    }
+
+    #[test]
+    fn single_and_multi_values_agree() {
+        // One component is typed like a component of a list.
+        assert_eq!(value_of("margin: 10px;"), px(10.0));
+        assert_eq!(value_of("width: 1.5em;"), Value::Length(1.5, Unit::Em));
+        assert_eq!(
+            value_of("margin: 1.5em 2em;"),
+            Value::List(vec![
+                Value::Length(1.5, Unit::Em),
+                Value::Length(2.0, Unit::Em)
+            ])
+        );
+        assert_eq!(value_of("padding-top: -5px;"), px(-5.0));
+        assert_eq!(value_of("display: block ;"), other("block"));
+        assert_eq!(
+            value_of("background: #ff0000;"),
+            Value::Color(Color::new(1.0, 0.0, 0.0, 1.0))
+        );
+
+        // Unitless numbers are not lengths except 0 or for a length property.
+        assert_eq!(value_of("font-weight: 600;"), other("600"));
+        assert_eq!(value_of("opacity: 0.5;"), other("0.5"));
+        assert_eq!(value_of("z-index: 0;"), px(0.0));
+        assert_eq!(value_of("width: 10;"), px(10.0));
+        assert_eq!(value_of("width: auto;"), px(0.0));
+        // translate_color turns what it does not know into black.
+        assert_eq!(
+            value_of("color: inherit;"),
+            Value::Color(Color::new(0.0, 0.0, 0.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn lists_through_getters() {
+        let style = ComputedStyle {
+            properties: [
+                ("margin", value_of("margin: auto 10px 0 5px;")),
+                ("padding", value_of("padding: 4px;")),
+                ("font-family", value_of("font-family: a, b;")),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+        };
+
+        assert_eq!(style.num_or("margin", 1.0), 10.0);
+        assert_eq!(style.num_or("padding", 1.0), 4.0);
+        assert_eq!(style.num_or("font-family", 1.0), 1.0);
+        assert_eq!(
+            style.values("margin"),
+            Some(&[other("auto"), px(10.0), px(0.0), px(5.0)][..])
+        );
+        assert_eq!(style.values("padding"), Some(&[px(4.0)][..]));
+        assert_eq!(
+            style.values("font-family"),
+            Some(&[other("a"), other("b")][..])
+        );
+        assert_eq!(style.values("width"), None);
+    }
+}
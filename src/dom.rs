@@ -1,6 +1,8 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 
+use crate::url;
+
 #[derive(PartialEq, Eq)]
 pub struct Node {
     pub children: Vec<Node>,
@@ -18,13 +20,17 @@ pub enum NodeType {
 pub struct ElementData {
     pub tag_name: String,
     attributes: AttrMap,
+    // The class attribute split into tokens once, see classes().
+    classes: Vec<String>,
 }
 
 impl ElementData {
     pub fn new(tag_name: String, attributes: AttrMap) -> ElementData {
+        let classes = split_classes(attributes.get("class").map_or("", String::as_str));
         ElementData {
             tag_name,
             attributes,
+            classes,
         }
     }
 
@@ -32,12 +38,39 @@ impl ElementData {
         self.attributes.get("id")
     }
 
-    pub fn get_classes(&self) -> HashSet<&str>{
-        match self.attributes.get("class"){
-            Some(s) => s.split(' ').collect(),
-            None => HashSet::new(),
+    pub fn attributes(&self) -> &AttrMap {
+        &self.attributes
+    }
+
+    // Classes in document order without duplicates, empty without a class attribute.
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    #[deprecated(note = "use classes() for the ordered list or has_class() to test a class")]
+    pub fn get_classes(&self) -> HashSet<&str> {
+        self.classes.iter().map(String::as_str).collect()
+    }
+
+    // Whole token and case-sensitive: class="btn-primary" has "btn-primary" but not "btn" or "BTN-primary".
+    pub fn has_class(&self, class: &str) -> bool {
+        self.classes.iter().any(|c| c == class)
+    }
+}
+
+/* Splits a class attribute on ASCII whitespace (space, tab, newline, form feed, carriage return),
+keeping the first occurrence of each class.
+    " a  b\tc a "  ->  ["a", "b", "c"]
+    "   "          ->  []
+*/
+fn split_classes(class: &str) -> Vec<String> {
+    let mut classes: Vec<String> = Vec::new();
+    for token in class.split(|c: char| c.is_ascii_whitespace()) {
+        if !token.is_empty() && !classes.iter().any(|c| c == token) {
+            classes.push(token.to_string());
         }
     }
+    classes
 }
 
 pub type AttrMap = HashMap<String, String>;
@@ -49,6 +82,88 @@ impl Node {
             children,
         }
     }
+
+    /* The node itself followed by all nodes below it, in pre-order (document order).
+
+        <div><p>a</p><b>c</b></div>   ->   div, p, "a", b, "c"
+
+    Walks with an explicit stack, so very deep trees do not overflow the call stack.
+    */
+    pub fn descendants(&self) -> Descendants<'_> {
+        Descendants { stack: vec![self] }
+    }
+
+    // Same order as descendants(), elements only.
+    pub fn descendant_elements(&self) -> impl Iterator<Item = (&Node, &ElementData)> {
+        self.descendants().filter_map(as_element)
+    }
+
+    // Direct children that are elements, in document order.
+    pub fn children_elements(&self) -> impl Iterator<Item = (&Node, &ElementData)> {
+        self.children.iter().filter_map(as_element)
+    }
+
+    /* Merges adjacent text children into one text node and removes text nodes that are
+    empty after merging, for the whole subtree (like DOM's normalize()).
+    Comments and elements are kept as they are, a comment between two texts keeps them apart.
+
+        ["Hello, ", "world", "!"]  ->  ["Hello, world!"]
+    */
+    pub fn normalize(&mut self) {
+        normalize_nodes(&mut self.children);
+    }
+
+    // First node in descendants() order (self included) matching pred.
+    pub fn find(&self, pred: impl Fn(&Node) -> bool) -> Option<&Node> {
+        self.descendants().find(|node| pred(node))
+    }
+}
+
+// Node::normalize for a list of sibling nodes, e.g. the top level nodes returned by the parser.
+pub fn normalize_nodes(nodes: &mut Vec<Node>) {
+    let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+
+    for mut node in nodes.drain(..) {
+        if let NodeType::Text(ref text) = node.node_type {
+            if let Some(Node {
+                node_type: NodeType::Text(ref mut previous),
+                ..
+            }) = merged.last_mut()
+            {
+                previous.push_str(text);
+                continue;
+            }
+        } else {
+            node.normalize();
+        }
+        merged.push(node);
+    }
+
+    merged.retain(|node| !matches!(node.node_type, NodeType::Text(ref t) if t.is_empty()));
+    *nodes = merged;
+}
+
+fn as_element(node: &Node) -> Option<(&Node, &ElementData)> {
+    match node.node_type {
+        NodeType::Element(ref e) => Some((node, e)),
+        _ => None,
+    }
+}
+
+// Iterator returned by Node::descendants.
+pub struct Descendants<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        let node = self.stack.pop()?;
+        // Pushed in reverse so the first child comes out next.
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
 }
 
 impl fmt::Debug for Node {
@@ -69,29 +184,398 @@ impl fmt::Debug for NodeType {
 impl fmt::Debug for ElementData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut attributes_string = String::new();
+        // Sorted by name so the output is the same from run to run.
+        let mut names: Vec<&String> = self.attributes.keys().collect();
+        names.sort();
 
-        for (attr, value) in self.attributes.iter() {
-            attributes_string.push_str(&format!(" {}=\"{}\"", attr, value));
+        for attr in names {
+            attributes_string.push_str(&format!(" {}=\"{}\"", attr, self.attributes[attr]));
         }
         write!(f, "<{},{}>", self.tag_name, attributes_string)
     }
 }
 
-pub fn pretty_print(n: &Node, indent_size: usize){
+// Attributes holding a URL (or a list of them for srcset) that resolve_urls rewrites.
+const URL_ATTRIBUTES: [&str; 6] = ["href", "src", "srcset", "action", "poster", "data"];
+
+/* Rewrites every URL attribute in the tree to an absolute URL.
+Relative URLs are resolved against the href of the first <base> element in the document
+(itself resolved against base) or against base when there is none.
+
+    base: https://example.com/blog/post.html
+    <img src="img/a.png">  ->  <img src="https://example.com/blog/img/a.png">
+*/
+pub fn resolve_urls(root: &mut Node, base: &str) {
+    let base = match find_base_href(root) {
+        Some(href) => url::resolve(base, &href),
+        None => base.to_string(),
+    };
+    resolve_node_urls(root, &base);
+}
+
+fn find_base_href(node: &Node) -> Option<String> {
+    if let NodeType::Element(ref e) = node.node_type {
+        if e.tag_name == "base" {
+            if let Some(href) = e.attributes.get("href") {
+                return Some(href.clone());
+            }
+        }
+    }
+    node.children.iter().find_map(find_base_href)
+}
+
+fn resolve_node_urls(node: &mut Node, base: &str) {
+    if let NodeType::Element(ref mut e) = node.node_type {
+        for (name, value) in e.attributes.iter_mut() {
+            if !URL_ATTRIBUTES.contains(&name.as_str()) {
+                continue;
+            }
+            *value = if name == "srcset" {
+                resolve_srcset(base, value)
+            } else {
+                url::resolve(base, value)
+            };
+        }
+    }
+    for child in node.children.iter_mut() {
+        resolve_node_urls(child, base);
+    }
+}
+
+// srcset is a comma separated list of "url [descriptor]", only the urls are resolved.
+fn resolve_srcset(base: &str, srcset: &str) -> String {
+    let mut candidates = Vec::new();
+    for candidate in srcset.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        candidates.push(match candidate.split_once(char::is_whitespace) {
+            Some((src, size)) => format!("{} {}", url::resolve(base, src), size.trim()),
+            None => url::resolve(base, candidate),
+        });
+    }
+    candidates.join(", ")
+}
+
+// Options for write_tree here and in style. The default is what pretty_print and Display print.
+#[derive(Clone, Copy, Debug)]
+pub struct PrintOptions {
+    // Spaces added per level.
+    pub indent: usize,
+    // style::write_tree only: append the property map to each node, sorted by property name.
+    pub show_properties: bool,
+    // Text and comment nodes. StyledNode::new only keeps elements, so styled trees have none yet.
+    pub include_text: bool,
+    // Nodes deeper than this are left out, the root is at depth 0.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            indent: 2,
+            show_properties: true,
+            include_text: true,
+            max_depth: None,
+        }
+    }
+}
+
+/* Writes node and its descendants to out, one line per node and a closing line per element,
+children indented below their parent. Walks the tree with an explicit stack so deep trees can
+not overflow it.
+
+    Example (default options)
+    <div, class="a">
+      <!-- note -->
+      <p,>
+        hello
+      <p/>
+    <div/>
+*/
+pub fn write_tree<W: fmt::Write>(node: &Node, out: &mut W, opts: &PrintOptions) -> fmt::Result {
+    // (node, depth, whether its children were written and only the closing line is left)
+    let mut stack = vec![(node, 0, false)];
+
+    while let Some((node, depth, closing)) = stack.pop() {
+        let indent = " ".repeat(depth * opts.indent);
+        match node.node_type {
+            NodeType::Element(ref e) if closing => writeln!(out, "{}<{}/>", indent, e.tag_name)?,
+            _ if matches!(opts.max_depth, Some(max) if depth > max) => {}
+            NodeType::Element(ref e) => {
+                writeln!(out, "{}{:?}", indent, e)?;
+                stack.push((node, depth, true));
+                // Reversed so the first child is popped first.
+                for child in node.children.iter().rev() {
+                    stack.push((child, depth + 1, false));
+                }
+            }
+            _ if !opts.include_text => {}
+            NodeType::Text(ref t) => writeln!(out, "{}{}", indent, t)?,
+            NodeType::Comment(ref c) => writeln!(out, "{}<!--{}-->", indent, c)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tree(self, f, &PrintOptions::default())
+    }
+}
+
+// Prints the tree to stdout, every line indented by indent_size more spaces.
+pub fn pretty_print(n: &Node, indent_size: usize) {
     let indent = (0..indent_size).map(|_| " ").collect::<String>();
+    for line in n.to_string().lines() {
+        println!("{}{}", indent, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parse::HtmlParser;
+
+    fn parse(html: &str) -> Node {
+        HtmlParser::new(html).parse_nodes().remove(0)
+    }
+
+    fn attr<'a>(node: &'a Node, tag: &str, name: &str) -> &'a str {
+        node.descendant_elements()
+            .find(|(_, e)| e.tag_name == tag)
+            .and_then(|(_, e)| e.attributes().get(name))
+            .map(String::as_str)
+            .unwrap()
+    }
+
+    fn labels<'a>(nodes: impl Iterator<Item = &'a Node>) -> Vec<String> {
+        nodes
+            .map(|node| match node.node_type {
+                NodeType::Element(ref e) => e.tag_name.clone(),
+                NodeType::Text(ref t) => format!("{:?}", t),
+                NodeType::Comment(ref c) => format!("<!--{}-->", c),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn iterator_order() {
+        let root = parse("<div><p>a<i>b</i></p><!--c--><ul><li>d</li><li>e</li></ul>f</div>");
+
+        assert_eq!(
+            labels(root.descendants()),
+            [
+                "div", "p", "\"a\"", "i", "\"b\"", "<!--c-->", "ul", "li", "\"d\"", "li", "\"e\"",
+                "\"f\""
+            ]
+        );
+        assert_eq!(
+            labels(root.descendant_elements().map(|(n, _)| n)),
+            ["div", "p", "i", "ul", "li", "li"]
+        );
+        assert_eq!(
+            labels(root.children_elements().map(|(n, _)| n)),
+            ["p", "ul"]
+        );
+        assert_eq!(
+            labels(
+                root.find(|n| matches!(n.node_type, NodeType::Text(_)))
+                    .into_iter()
+            ),
+            ["\"a\""]
+        );
+        assert!(root.find(|n| n.children.len() > 4).is_none());
+        // A leaf yields only itself.
+        assert_eq!(labels(root.children[3].descendants()), ["\"f\""]);
+    }
+
+    fn text(t: &str) -> Node {
+        Node::new(NodeType::Text(t.to_string()), Vec::new())
+    }
+
+    #[test]
+    fn normalize() {
+        let mut root = Node::new(
+            NodeType::Element(ElementData::new("p".to_string(), AttrMap::new())),
+            vec![
+                text("Hello, "),
+                text(""),
+                text("world"),
+                text("!"),
+                Node::new(NodeType::Comment("c".to_string()), Vec::new()),
+                text(""),
+                Node::new(
+                    NodeType::Element(ElementData::new("b".to_string(), AttrMap::new())),
+                    vec![text("a"), text(""), text("b")],
+                ),
+                text(""),
+            ],
+        );
+        root.normalize();
+
+        assert_eq!(
+            labels(root.descendants()),
+            ["p", "\"Hello, world!\"", "<!--c-->", "b", "\"ab\""]
+        );
+    }
+
+    fn with_class(class: &str) -> ElementData {
+        let mut attributes = AttrMap::new();
+        attributes.insert("class".to_string(), class.to_string());
+        ElementData::new("div".to_string(), attributes)
+    }
+
+    #[test]
+    fn classes() {
+        let cases: [(&str, &[&str]); 6] = [
+            (" a  b\tc ", &["a", "b", "c"]),
+            ("b a b\na", &["b", "a"]),
+            ("one\r\ntwo\x0cthree", &["one", "two", "three"]),
+            ("", &[]),
+            (" \t\n ", &[]),
+            ("Btn btn", &["Btn", "btn"]),
+        ];
+        for (class, expected) in cases {
+            assert_eq!(with_class(class).classes(), expected, "{:?}", class);
+        }
+        let no_class = ElementData::new("div".to_string(), AttrMap::new());
+        assert!(no_class.classes().is_empty());
 
-    match n.node_type {
-        NodeType::Element(ref e) => println!("{}{:?}", indent, e),
-        NodeType::Text(ref t) => println!("{}{}", indent, t),
-        NodeType::Comment(ref c) => println!("{}<!--{}-->", indent, c),
+        let e = with_class(" a  b\tc ");
+        assert!(e.has_class("a") && e.has_class("b") && e.has_class("c"));
+        assert!(!e.has_class("A"));
+        assert!(!e.has_class("a  b"));
+        assert!(!e.has_class(""));
+        let e = with_class("btn-primary");
+        assert!(!e.has_class("btn"));
     }
 
-    for child in n.children.iter(){
-        pretty_print(&child, indent_size+2);
+    const PRINT_DOC: &str = "<div class=\"a\"><!-- note --><p>hello <b>world</b></p><br></div>";
+
+    #[test]
+    fn write_tree_default() {
+        let root = parse(PRINT_DOC);
+        let expected = "\
+<div, class=\"a\">
+  <!-- note -->
+  <p,>
+    hello 
+    <b,>
+      world
+    <b/>
+  <p/>
+  <br,>
+  <br/>
+<div/>
+";
+        assert_eq!(root.to_string(), expected);
+    }
+
+    #[test]
+    fn write_tree_options() {
+        let root = parse(PRINT_DOC);
+        let options = PrintOptions {
+            indent: 4,
+            include_text: false,
+            max_depth: Some(1),
+            ..PrintOptions::default()
+        };
+        let mut out = String::new();
+        write_tree(&root, &mut out, &options).unwrap();
+
+        let expected = "\
+<div, class=\"a\">
+    <p,>
+    <p/>
+    <br,>
+    <br/>
+<div/>
+";
+        assert_eq!(out, expected);
     }
 
-    match n.node_type{
-        NodeType::Element(ref e) => println!("{}<{}/>", indent, e.tag_name),
-        _ => {}
+    #[test]
+    fn write_tree_sorts_attributes() {
+        // Each parse gets a freshly seeded attribute map, so repeat to catch any unsorted output.
+        for _ in 0..8 {
+            let root =
+                parse("<a id=\"y\" href=\"x\" title=\"t\" class=\"z\" data-n=\"1\">link</a>");
+            let expected = "\
+<a, class=\"z\" data-n=\"1\" href=\"x\" id=\"y\" title=\"t\">
+  link
+<a/>
+";
+            assert_eq!(root.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn deep_tree() {
+        const DEPTH: usize = 20_000;
+        let mut node = Node::new(NodeType::Text("leaf".to_string()), Vec::new());
+        for _ in 0..DEPTH {
+            let elem = ElementData::new("div".to_string(), AttrMap::new());
+            node = Node::new(NodeType::Element(elem), vec![node]);
+        }
+
+        assert_eq!(node.descendants().count(), DEPTH + 1);
+        assert_eq!(node.descendant_elements().count(), DEPTH);
+        let leaf = node.find(|n| n.children.is_empty()).unwrap();
+        assert_eq!(leaf.node_type, NodeType::Text("leaf".to_string()));
+
+        // Dropping is recursive, take the tree apart level by level instead.
+        let mut children = node.children;
+        while let Some(mut child) = children.pop() {
+            children.append(&mut child.children);
+        }
+    }
+
+    #[test]
+    fn resolve_urls_in_tree() {
+        let mut root = parse(
+            "<div><a href=\"../up.html\">x</a><img src=\"/logo.png\" \
+             srcset=\"a.png 1x, ../b.png 2x\"><form action=\"\"></form>\
+             <video poster=\"https://cdn.example.com/p.jpg\"></video><p title=\"t.html\"></p></div>",
+        );
+        resolve_urls(&mut root, "https://example.com/blog/post.html");
+
+        assert_eq!(attr(&root, "a", "href"), "https://example.com/up.html");
+        assert_eq!(attr(&root, "img", "src"), "https://example.com/logo.png");
+        assert_eq!(
+            attr(&root, "img", "srcset"),
+            "https://example.com/blog/a.png 1x, https://example.com/b.png 2x"
+        );
+        assert_eq!(
+            attr(&root, "form", "action"),
+            "https://example.com/blog/post.html"
+        );
+        assert_eq!(
+            attr(&root, "video", "poster"),
+            "https://cdn.example.com/p.jpg"
+        );
+        // Not a URL attribute.
+        assert_eq!(attr(&root, "p", "title"), "t.html");
+    }
+
+    #[test]
+    fn resolve_urls_with_base_element() {
+        let mut root = parse(
+            "<html><head><base href=\"/static/\"><base href=\"/ignored/\"></head>\
+             <body><a href=\"x/y.html\">x</a><a href=\"#top\">top</a></body></html>",
+        );
+        resolve_urls(&mut root, "https://example.com/blog/post.html");
+
+        let hrefs: Vec<&str> = root
+            .descendant_elements()
+            .filter(|(_, e)| e.tag_name == "a")
+            .map(|(_, e)| e.attributes()["href"].as_str())
+            .collect();
+        assert_eq!(
+            hrefs,
+            [
+                "https://example.com/static/x/y.html",
+                "https://example.com/static/#top"
+            ]
+        );
+        // The base element itself is resolved against the document URL.
+        assert_eq!(attr(&root, "base", "href"), "https://example.com/static/");
     }
 }
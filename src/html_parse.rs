@@ -1,18 +1,36 @@
-use std::iter::Peekable;
-use std::str::Chars;
+use crate::dom::{self, AttrMap, ElementData, Node, NodeType};
 
-use crate::dom::{AttrMap, ElementData, Node, NodeType};
+// Options for HtmlParser::with_options, all off by default (same output as HtmlParser::new).
+#[derive(Clone, Copy, Default)]
+pub struct ParseOptions {
+    // Run Node::normalize on the result: adjacent text nodes merged, empty text nodes removed.
+    pub normalize: bool,
+}
 
 pub struct HtmlParser<'a> {
-    chars: Peekable<Chars<'a>>,
+    input: &'a str,
+    pos: usize,
     node_q: Vec<String>,
+    open_tags: Vec<String>,
+    pending_start: Option<String>,
+    pending_close: Option<String>,
+    options: ParseOptions,
 }
 
 impl<'a> HtmlParser<'a> {
     pub fn new(full_html: &str) -> HtmlParser {
+        HtmlParser::with_options(full_html, ParseOptions::default())
+    }
+
+    pub fn with_options(full_html: &str, options: ParseOptions) -> HtmlParser<'_> {
         HtmlParser {
-            chars: full_html.chars().peekable(),
+            input: full_html,
+            pos: 0,
             node_q: Vec::new(),
+            open_tags: Vec::new(),
+            pending_start: None,
+            pending_close: None,
+            options,
         }
     }
 
@@ -100,7 +118,23 @@ impl<'a> HtmlParser<'a> {
 
 
     pub fn parse_nodes(&mut self) -> Vec<Node> {
+        let mut nodes = self.parse_nodes_in(false).0;
+        if self.options.normalize {
+            dom::normalize_nodes(&mut nodes);
+        }
+        nodes
+    }
+
+    /* Same as parse_nodes() but knows whether we are inside a <pre>/<textarea>.
+    When preformatted is true whitespace is never skipped between nodes and text
+    nodes are kept verbatim, so the flag is passed down to every nested element.
+
+    Also returns whether the nodes were ended by a close tag (a real one or an implied one)
+    rather than by the end of the input, see parse_element().
+    */
+    fn parse_nodes_in(&mut self, preformatted: bool) -> (Vec<Node>, bool) {
         let mut nodes = Vec::new();
+        let mut closed = false;
 
         /* first check if '<' -> then either in order as checked
         * closing tag (</div>)
@@ -110,55 +144,164 @@ impl<'a> HtmlParser<'a> {
         else -> text node (hello) in <p>hello</p>
         */
 
-        while self.chars.peek().is_some() {
-            self.consume_while(char::is_whitespace);
-            if self.chars.peek().map_or(false, |c| *c == '<') {
-                self.chars.next();
-                if self.chars.peek().map_or(false, |c| *c == '/') {
-                    self.chars.next();
+        loop {
+            // A tag read by a child that turned out to close it implicitly is handled here first.
+            if let Some(close_tag_name) = self.pending_close.take() {
+                self.close_element(close_tag_name);
+                closed = true;
+                break;
+            }
+            if let Some(tagname) = self.pending_start.take() {
+                if !self.parse_element(tagname, preformatted, &mut nodes) {
+                    closed = true;
+                    break;
+                }
+                continue;
+            }
+            if self.peek().is_none() {
+                break;
+            }
+
+            if !preformatted {
+                self.consume_while(char::is_whitespace);
+            }
+            if self.peek() == Some('<') {
+                self.next_char();
+                if self.peek() == Some('/') {
+                    self.next_char();
                     self.consume_while(char::is_whitespace);
 
-                    let close_tag_name = self.consume_while(is_valid_tag_name);
-                    self.consume_while(|x| x != '>');
-                    self.chars.next();
-                    self.node_q.push(close_tag_name);
+                    let close_tag_name = self.consume_while(is_valid_tag_name).to_string();
+                    self.consume_until(b'>');
+                    // A close tag without a name (</>) or cut off by the end of input closes nothing.
+                    if self.next_char().is_none() || close_tag_name.is_empty() {
+                        continue;
+                    }
+                    self.close_element(close_tag_name);
+                    closed = true;
                     break;
-                } else if self.chars.peek().map_or(false, |c| *c == '!') {
-                    self.chars.next();
+                } else if self.peek() == Some('!') {
+                    self.next_char();
                     nodes.push(self.parse_comment_node());
+                } else if self.peek().map_or(false, is_valid_tag_name) {
+                    let tagname = self.consume_while(is_valid_tag_name).to_string();
+                    if !self.parse_element(tagname, preformatted, &mut nodes) {
+                        closed = true;
+                        break;
+                    }
                 } else {
-                    let mut node = self.parse_node();
-                    let insert_index = nodes.len();
-
-                    
-                    match node.node_type {
-                        NodeType::Element(ref e) => {
-                            if self.node_q.len() > 0 {
-                                let assumed_tag = self.node_q.remove(0);
-
-                                if e.tag_name != assumed_tag {
-                                    nodes.append(&mut node.children);
-                                    self.node_q.insert(0, assumed_tag);
-                                }
-                            }
-                        }
-                        _ => {}
+                    // A '<' that does not start a tag ("a < b", or the last char of the input) is text.
+                    let mut text_node = self.parse_text_node(preformatted);
+                    if let NodeType::Text(ref mut text) = text_node.node_type {
+                        text.insert(0, '<');
                     }
-                    nodes.insert(insert_index, node);
+                    nodes.push(text_node);
                 }
             } else {
-                nodes.push(self.parse_text_node());
+                nodes.push(self.parse_text_node(preformatted));
             }
         }
-        nodes
+        (nodes, closed)
+    }
+
+    /* Parses the element whose tag name was just read and adds it to nodes.
+    Returns false (without parsing anything) when the tag implicitly ends the element
+    that is currently open, e.g. <li> while an <li> is open. The open element is then
+    closed as if its close tag had been seen and the new tag is left in pending_start
+    for the parent to parse.
+
+    End of input: elements still open when the input runs out are closed where they are,
+    keeping every child parsed so far (<div><p>hello -> div > p > "hello"). They skip the
+    node_q check as no close tag was seen for them. A start tag cut off before its '>'
+    (<a href="x) still becomes an element with the attributes read so far.
+    */
+    fn parse_element(
+        &mut self,
+        tagname: String,
+        preformatted: bool,
+        nodes: &mut Vec<Node>,
+    ) -> bool {
+        if let Some(open_tag) = self.implied_end(&tagname) {
+            self.node_q.push(open_tag);
+            self.pending_start = Some(tagname);
+            return false;
+        }
+
+        let (mut node, closed) = self.parse_node(tagname, preformatted);
+        let insert_index = nodes.len();
+
+        match node.node_type {
+            NodeType::Element(ref e) if closed => {
+                if self.node_q.len() > 0 {
+                    let assumed_tag = self.node_q.remove(0);
+
+                    if e.tag_name != assumed_tag {
+                        nodes.append(&mut node.children);
+                        self.node_q.insert(0, assumed_tag);
+                    }
+                }
+            }
+            _ => {}
+        }
+        nodes.insert(insert_index, node);
+        true
+    }
+
+    /* Handles a close tag for the element currently open.
+    If the open element has an optional end tag (li, p, td, ...) and the close tag is meant
+    for one of its ancestors (<li>two</ul>), the open element is closed first and the
+    close tag is kept in pending_close so the parent sees it next.
+    */
+    fn close_element(&mut self, close_tag_name: String) {
+        match self.open_tags.last() {
+            Some(open_tag)
+                if *open_tag != close_tag_name
+                    && IMPLIED_END_TAGS.iter().any(|(tag, _)| tag == open_tag)
+                    && self.open_tags.contains(&close_tag_name) =>
+            {
+                self.node_q.push(open_tag.clone());
+                self.pending_close = Some(close_tag_name);
+            }
+            _ => self.node_q.push(close_tag_name),
+        }
+    }
+
+    // Whether an <svg> or <math> element is open, see parse_node().
+    fn in_foreign_content(&self) -> bool {
+        self.open_tags.iter().any(|tag| is_foreign_root(tag))
+    }
+
+    /* Name of the open element to close because a start tag with this name implicitly ends an
+    open element, if any. The open elements are checked from the innermost one, passing over
+    phrasing elements (<b>, <a>, ...) and optional-end elements the tag does not end, up to the
+    element it ends. Anything else (a <div>, a <ul>) stops the search.
+
+    Only the innermost element is returned, the parent sees the start tag again in pending_start
+    and closes the next one, until the ended element itself is closed.
+        <ul><li><b>one<li>two  ->  b is closed, then li, then the new li is parsed under ul
+    */
+    fn implied_end(&self, tagname: &str) -> Option<String> {
+        let innermost = self.open_tags.last()?;
+
+        for open_tag in self.open_tags.iter().rev() {
+            match IMPLIED_END_TAGS.iter().find(|(tag, _)| tag == open_tag) {
+                Some((_, closed_by)) if closed_by.contains(&tagname) => {
+                    return Some(innermost.clone())
+                }
+                Some(_) => {}
+                None if PHRASING_TAGS.contains(&open_tag.as_str()) => {}
+                None => return None,
+            }
+        }
+        None
     }
 
 /*
         The parse_node() function is responsible for parsing an HTML element and its attributes, then recursively parsing its child nodes.
     How It Works
 
-        Extract the tag name
-            Uses consume_while(is_valid_tag_name) to read the tag name.
+        Get the tag name
+            parse_nodes() reads it with consume_while(is_valid_tag_name) and passes it in.
         Parse attributes
             Calls parse_attributes() to extract any attributes associated with the tag.
         Create an ElementData instance
@@ -205,179 +348,331 @@ impl<'a> HtmlParser<'a> {
     │
 */
 
-    fn parse_node(&mut self) -> Node {
-        let tagname = self.consume_while(is_valid_tag_name);
-        let attributes = self.parse_attributes();
+    /* Inside <svg> and <math> (foreign content) attribute names keep their case (viewBox,
+    gradientUnits) and a start tag ending in /> is self-closing: <path d="M0 0"/> gets no
+    children and the next tag becomes its sibling. Such an element is returned as not closed,
+    as no close tag was seen for it. Outside foreign content /> is ignored.
+    */
+    fn parse_node(&mut self, tagname: String, preformatted: bool) -> (Node, bool) {
+        let foreign = self.in_foreign_content() || is_foreign_root(&tagname);
+        let (attributes, self_closing) = self.parse_attributes(foreign);
+        if foreign && self_closing {
+            let elem = ElementData::new(tagname, attributes);
+            return (Node::new(NodeType::Element(elem), Vec::new()), false);
+        }
+
+        // A single newline right after <pre> / <textarea> is not part of the content.
+        let starts_preformatted = PREFORMATTED_TAGS.contains(&tagname.as_str());
+        if starts_preformatted {
+            self.next_if_eq('\r');
+            self.next_if_eq('\n');
+        }
+
+        self.open_tags.push(tagname.clone());
+        let (children, closed) = self.parse_nodes_in(preformatted || starts_preformatted);
+        self.open_tags.pop();
 
         let elem = ElementData::new(tagname, attributes);
-        let children = self.parse_nodes();
-        Node::new(NodeType::Element(elem), children)
+        (Node::new(NodeType::Element(elem), children), closed)
     }
 
-    fn parse_text_node(&mut self) -> Node {
+    /* Inside <pre>/<textarea> the text is taken as is (newlines and repeated spaces included),
+    everywhere else every run of whitespace is collapsed to a single space.
+    */
+    fn parse_text_node(&mut self, preformatted: bool) -> Node {
+        if preformatted {
+            let text_content = self.consume_until(b'<').to_string();
+            return Node::new(NodeType::Text(text_content), Vec::new());
+        }
+
         let mut text_content = String::new();
 
-        while self.chars.peek().map_or(false, |c| *c != '<') {
+        while self.peek().map_or(false, |c| c != '<') {
             let whitespace = self.consume_while(char::is_whitespace);
             if whitespace.len() > 0 {
                 text_content.push(' ');
             }
             let text_part = self.consume_while(|x| !x.is_whitespace() && x != '<');
-            text_content.push_str(&text_part);
+            text_content.push_str(text_part);
         }
         Node::new(NodeType::Text(text_content), Vec::new())
     }
 
+/*
+    parse_comment_node() is called right after "<!" was consumed and follows the comment
+    states of the HTML tokenizer. Whatever sits between "<!--" and the closing delimiter is kept as is.
+
+    Start      -> '-' goes to StartDash, '>' closes an empty comment (<!-->), anything else is text
+    StartDash  -> '-' goes to End, '>' closes an empty comment (<!--->), anything else is "-" + text
+    Text       -> '-' goes to EndDash, anything else (including "<!--") is text
+    EndDash    -> '-' goes to End, anything else is "-" + text
+    End        -> '>' closes, '!' goes to EndBang, '-' adds one "-" to the text, anything else is "--" + text
+    EndBang    -> '>' closes (--!>), '-' adds "--!" and goes to EndDash, anything else is "--!" + text
+
+    If the input runs out in any state the comment ends there with the text collected so far.
+    "<!" not followed by "--" (<!DOCTYPE html>, <!x>) is a bogus comment running up to the next '>'.
+
+    Example
+    <!-- a -- b --!>   ->   Comment(" a -- b ")
+*/
     fn parse_comment_node(&mut self) -> Node {
         let mut comment_content = String::new();
 
-        if self.chars.peek().map_or(false, |c| *c == '-') {
-            self.chars.next();
-            if self.chars.peek().map_or(false, |c| *c == '-') {
-                self.chars.next();
-            } else {
-                self.consume_while(|c| c != '>');
-                return Node::new(NodeType::Comment(comment_content), Vec::new());
+        if self.next_if_eq('-') {
+            if !self.next_if_eq('-') {
+                comment_content.push('-');
+                return self.parse_bogus_comment(comment_content);
             }
         } else {
-            self.consume_while(|c| c != '>');
-            return Node::new(NodeType::Comment(comment_content), Vec::new());
-        }
-        if self.chars.peek().map_or(false, |c| *c == '>') {
-            self.chars.next();
-            return Node::new(NodeType::Comment(comment_content), Vec::new());
+            return self.parse_bogus_comment(comment_content);
         }
 
-        if self.chars.peek().map_or(false, |c| *c == '-') {
-            self.chars.next();
-            if self.chars.peek().map_or(false, |c| *c == '>') {
-                self.chars.next();
-                return Node::new(NodeType::Comment(comment_content), Vec::new());
-            } else {
-                comment_content.push('-');
-            }
-        }
+        let mut state = CommentState::Start;
 
-        while self.chars.peek().is_some() {
-            comment_content.push_str(&self.consume_while(|c| c != '<' && c != '-'));
-            if self.chars.peek().map_or(false, |c| *c == '<') {
-                self.chars.next();
-                if self.chars.peek().map_or(false, |c| *c == '!') {
-                    self.chars.next();
-                    if self.chars.peek().map_or(false, |c| *c == '-') {
-                        self.chars.next();
-                        if self.chars.peek().map_or(false, |c| *c == '-') {
-                            self.consume_while(|c| c != '>');
-
-                            return Node::new(NodeType::Comment(String::from("")), Vec::new());
-                        } else {
-                            comment_content.push_str("<!-");
-                        }
-                    } else if self.chars.peek().map_or(false, |c| *c == ' ') {
-                        self.chars.next();
-                        if self.chars.peek().map_or(false, |c| *c == '-') {
-                            self.chars.next();
-                            if self.chars.peek().map_or(false, |c| *c == '-') {
-                                self.chars.next();
-                                if self.chars.peek().map_or(false, |c| *c == '-') {
-                                    self.chars.next();
-                                    if self.chars.peek().map_or(false, |c| *c == '>') {
-                                        self.chars.next();
-                                        return Node::new(
-                                            NodeType::Comment(String::from("")),
-                                            Vec::new(),
-                                        );
-                                    } else {
-                                        comment_content.push_str("<! --");
-                                    }
-                                } else {
-                                    comment_content.push_str("<! -");
-                                }
-                            } else {
-                                comment_content.push_str("<! ");
-                            }
-                        }
-                    } else {
-                        comment_content.push_str("<!");
-                    }
-                } else {
-                    comment_content.push('<');
+        while let Some(c) = self.peek() {
+            // Every arm consumes c except the ones that hand it over to Text (or scan ahead themselves).
+            state = match (state, c) {
+                (CommentState::Start, '-') => CommentState::StartDash,
+                (CommentState::Start, '>') | (CommentState::StartDash, '>') => {
+                    self.next_char();
+                    break;
                 }
-            } else if self.chars.peek().map_or(false, |c| *c == '-') {
-                self.chars.next();
-                if self.chars.peek().map_or(false, |c| *c == '-') {
-                    self.chars.next();
-                    if self.chars.peek().map_or(false, |c| *c == '>') {
-                        self.chars.next();
-                        break;
-                    } else {
-                        comment_content.push_str("--");
-                    }
-                } else {
+                (CommentState::Start, _) => {
+                    state = CommentState::Text;
+                    continue;
+                }
+                (CommentState::StartDash, '-') | (CommentState::EndDash, '-') => CommentState::End,
+                (CommentState::StartDash, _) | (CommentState::EndDash, _) => {
                     comment_content.push('-');
+                    state = CommentState::Text;
+                    continue;
                 }
-            }
+                (CommentState::Text, '-') => CommentState::EndDash,
+                (CommentState::Text, _) => {
+                    comment_content.push_str(self.consume_until(b'-'));
+                    continue;
+                }
+                (CommentState::End, '>') | (CommentState::EndBang, '>') => {
+                    self.next_char();
+                    break;
+                }
+                (CommentState::End, '!') => CommentState::EndBang,
+                (CommentState::End, '-') => {
+                    comment_content.push('-');
+                    CommentState::End
+                }
+                (CommentState::End, _) => {
+                    comment_content.push_str("--");
+                    state = CommentState::Text;
+                    continue;
+                }
+                (CommentState::EndBang, '-') => {
+                    comment_content.push_str("--!");
+                    CommentState::EndDash
+                }
+                (CommentState::EndBang, _) => {
+                    comment_content.push_str("--!");
+                    state = CommentState::Text;
+                    continue;
+                }
+            };
+            self.next_char();
         }
 
         Node::new(NodeType::Comment(comment_content), Vec::new())
     }
 
-    fn parse_attributes(&mut self) -> AttrMap {
+    fn parse_bogus_comment(&mut self, mut comment_content: String) -> Node {
+        comment_content.push_str(self.consume_until(b'>'));
+        self.next_char();
+        Node::new(NodeType::Comment(comment_content), Vec::new())
+    }
+
+/*
+    parse_attributes() reads everything between the tag name and the end of the tag ('>' or "/>").
+    Each attribute goes through the states of the HTML tokenizer:
+
+    before name  -> whitespace and a stray '/' are skipped, '>' or "/>" ends the tag
+    name         -> runs up to whitespace, '/', '>' or '='. The first char is always taken, even
+                    a quote or '=', so the loop can never get stuck on a char it does not expect
+    before value -> whitespace, then '=' means a value follows. No '=' is a boolean attribute ("")
+    value        -> quoted ("..." or '...') up to the matching quote, or unquoted up to
+                    whitespace or '>'. Nothing after the closing quote is skipped, so
+                    <input value="a"disabled> has both value and disabled. An unquoted value
+                    keeps a trailing '/' (<a href=/docs/> is href="/docs/"), so "/>" only
+                    ends the tag after whitespace, a quoted value or a name.
+
+    Names are lowercased (kept as written when foreign is set, inside <svg>/<math>) and only the
+    first occurrence of a name is kept, as in browsers. Also returns whether the tag ended with "/>".
+
+    Example
+    <input type = "checkbox" checked TYPE=radio/>  ->  {"type": "checkbox", "checked": ""}
+*/
+    fn parse_attributes(&mut self, foreign: bool) -> (AttrMap, bool) {
         let mut attributes = AttrMap::new();
+        let mut self_closing = false;
 
-        while self.chars.peek().map_or(false, |c| *c != '>') {
+        loop {
             self.consume_while(char::is_whitespace);
-            let name = self.consume_while(|c| is_valid_attr_name(c)).to_lowercase();
+            match self.peek() {
+                None => break,
+                Some('>') => {
+                    self.next_char();
+                    break;
+                }
+                Some('/') => {
+                    self.next_char();
+                    if self.next_if_eq('>') {
+                        self_closing = true;
+                        break;
+                    }
+                    continue;
+                }
+                Some(_) => {}
+            }
+
+            let mut name = String::new();
+            name.extend(self.next_char());
+            name.push_str(self.consume_while(is_valid_attr_name));
             self.consume_while(char::is_whitespace);
 
-            let value = if self.chars.peek().map_or(false, |c| *c == '=') {
-                self.chars.next();
-                self.consume_while(char::is_whitespace);
-                let s = self.parse_attr_value();
-                self.consume_while(|c| !c.is_whitespace() && c != '>');
-                self.consume_while(char::is_whitespace);
-                s
+            let value = if self.next_if_eq('=') {
+                self.parse_attr_value()
             } else {
-                "".to_string()
+                String::new()
             };
-            attributes.insert(name, value);
+            if !foreign {
+                name = name.to_lowercase();
+            }
+            attributes.entry(name).or_insert(value);
         }
-        self.chars.next();
-        attributes
+        (attributes, self_closing)
     }
 
     fn parse_attr_value(&mut self) -> String {
         self.consume_while(char::is_whitespace);
-        let result = match self.chars.peek() {
-            Some(&c) if c == '"' || c == '\'' => {
-                self.chars.next();
-                let ret = self.consume_while(|x| x != c);
-                self.chars.next();
-                ret
+        match self.peek() {
+            Some(c) if c == '"' || c == '\'' => {
+                self.next_char();
+                let value = self.consume_until(c as u8).to_string();
+                self.next_char();
+                value
             }
-            _ => self.consume_while(is_valid_attr_value),
-        };
-        result
+            _ => self.consume_while(is_valid_attr_value).to_string(),
+        }
     }
 
-    fn consume_while<F>(&mut self, condition: F) -> String
+    fn peek(&self) -> Option<char> {
+        match self.input.as_bytes().get(self.pos) {
+            Some(&b) if b.is_ascii() => Some(b as char),
+            Some(_) => self.input[self.pos..].chars().next(),
+            None => None,
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn next_if_eq(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.pos += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns the consumed part as a slice of the input, nothing is copied.
+    fn consume_while<F>(&mut self, condition: F) -> &'a str
     where
         F: Fn(char) -> bool,
     {
-        let mut result = String::new();
-        while self.chars.peek().map_or(false, |c| condition(*c)) {
-            result.push(self.chars.next().unwrap());
-        }
-        result
+        let rest = &self.input[self.pos..];
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| !condition(c))
+            .map_or(rest.len(), |(i, _)| i);
+        self.pos += end;
+        &rest[..end]
     }
+
+    /* Fast path of consume_while(|c| c != delimiter) for ASCII delimiters ('<', '>', quotes).
+    Scans bytes instead of decoding chars, an ASCII byte never appears inside a multi-byte char
+    so the returned slice always ends on a char boundary.
+    */
+    fn consume_until(&mut self, delimiter: u8) -> &'a str {
+        let rest = &self.input[self.pos..];
+        let end = rest
+            .as_bytes()
+            .iter()
+            .position(|&b| b == delimiter)
+            .unwrap_or(rest.len());
+        self.pos += end;
+        &rest[..end]
+    }
+}
+
+/* Optional end tags: an open element (left) is closed by a start tag in its list (right).
+    <ul><li>one<li>two</ul> -> ul has two li children instead of li nested in li.
+*/
+const IMPLIED_END_TAGS: [(&str, &[&str]); 9] = [
+    ("li", &["li"]),
+    (
+        "p",
+        &[
+            "address", "article", "aside", "blockquote", "center", "dd", "details", "dialog", "dir",
+            "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "h1", "h2",
+            "h3", "h4", "h5", "h6", "header", "hgroup", "hr", "li", "main", "menu", "nav", "ol",
+            "p", "pre", "section", "summary", "table", "ul",
+        ],
+    ),
+    ("dt", &["dt", "dd"]),
+    ("dd", &["dt", "dd"]),
+    ("td", &["td", "th", "tr", "tbody", "thead", "tfoot"]),
+    ("th", &["td", "th", "tr", "tbody", "thead", "tfoot"]),
+    ("tr", &["tr", "tbody", "thead", "tfoot"]),
+    ("option", &["option", "optgroup"]),
+    ("optgroup", &["optgroup"]),
+];
+
+// Inline elements that an implied end tag closes along with the element they are in, see implied_end().
+const PHRASING_TAGS: [&str; 27] = [
+    "a", "abbr", "b", "bdi", "bdo", "big", "cite", "code", "dfn", "em", "font", "i", "kbd", "label",
+    "mark", "nobr", "q", "s", "samp", "small", "span", "strike", "strong", "sub", "sup", "tt", "u",
+];
+
+#[derive(Clone, Copy)]
+enum CommentState {
+    Start,
+    StartDash,
+    Text,
+    EndDash,
+    End,
+    EndBang,
+}
+
+// Elements whose text content keeps its whitespace (code inside pre inherits it).
+const PREFORMATTED_TAGS: [&str; 2] = ["pre", "textarea"];
+
+// Elements that start foreign content (SVG and MathML), see parse_node().
+const FOREIGN_TAGS: [&str; 2] = ["svg", "math"];
+
+fn is_foreign_root(tagname: &str) -> bool {
+    FOREIGN_TAGS
+        .iter()
+        .any(|tag| tag.eq_ignore_ascii_case(tagname))
 }
+
 // .is_digit(36) allows: Alphanumeric characters (a-z, A-Z, 0-9).
 fn is_valid_tag_name(ch: char) -> bool {
     ch.is_digit(36)
 }
 
 /* Checks if a character can be part of an attribute name.
-Valid: class, id, data-value
+Valid: class, id, data-value, xmlns, xlink:href
 Invalid: Contains =, ", ', whitespace
 */
 fn is_valid_attr_name(c: char) -> bool {
@@ -406,13 +701,391 @@ fn is_excluded_name(c: char) -> bool {
     }
 }
 
-/* Defines valid characters for attribute values.
-    Valid: abc123
-    Invalid: ", ', =, <, >.
+/* Defines valid characters for unquoted attribute values.
+    Valid: abc123, 100%, a=b, /path/to
+    Invalid: whitespace, > (they end the value)
 */
 fn is_valid_attr_value(c: char) -> bool {
-    match c {
-        ' ' | '"' | '\'' | '=' | '<' | '>' | '`' => false,
-        _ => true,
+    !c.is_whitespace() && c != '>'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(html: &str) -> Vec<Node> {
+        HtmlParser::new(html).parse_nodes()
+    }
+
+    fn element(node: &Node) -> &ElementData {
+        match node.node_type {
+            NodeType::Element(ref e) => e,
+            _ => panic!("not an element: {:?}", node),
+        }
+    }
+
+    fn attr_map(pairs: &[(&str, &str)]) -> AttrMap {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn text_of(nodes: &[Node]) -> String {
+        let mut text = String::new();
+        for node in nodes.iter().flat_map(|n| n.descendants()) {
+            if let NodeType::Text(ref t) = node.node_type {
+                text.push_str(t);
+            }
+        }
+        text
+    }
+
+    const TRUNCATED_DOC: &str = "<div id=\"main\"><p class=\"intro\">Héllo → wörld</p>\
+        <!-- note --><a href=\"/x\">link</a></div>";
+
+    #[test]
+    fn truncated_at_every_offset() {
+        let full = parse(TRUNCATED_DOC);
+        let full_text = text_of(&full);
+        assert_eq!(full_text, "Héllo → wörldlink");
+
+        let p_closed = TRUNCATED_DOC.find("</p>").unwrap() + "</p>".len();
+        let mut previous_len = 0;
+
+        for end in (0..=TRUNCATED_DOC.len()).filter(|&i| TRUNCATED_DOC.is_char_boundary(i)) {
+            let prefix = &TRUNCATED_DOC[..end];
+            let nodes = parse(prefix);
+
+            // Text is never invented, a '<' cut off from its tag is text.
+            let text = text_of(&nodes);
+            let text = text.strip_suffix('<').unwrap_or(&text);
+            assert!(full_text.starts_with(text), "{:?} -> {:?}", prefix, text);
+            // Nor lost: a longer prefix never has less text.
+            assert!(text.len() >= previous_len, "{:?} -> {:?}", prefix, text);
+            previous_len = text.len();
+
+            // Once the paragraph is complete it stays in place, whatever comes after it.
+            if end >= p_closed {
+                let div = &nodes[0];
+                assert_eq!(element(div).tag_name, "div", "{:?}", prefix);
+                assert_eq!(element(&div.children[0]).tag_name, "p", "{:?}", prefix);
+                assert_eq!(text_of(&div.children[..1]), "Héllo → wörld", "{:?}", prefix);
+            }
+
+            // Every element is one of the document's, possibly with its start tag cut short.
+            for node in nodes.iter().flat_map(|n| n.descendants()) {
+                if let NodeType::Element(ref e) = node.node_type {
+                    assert!(
+                        ["div", "p", "a"].iter().any(|t| t.starts_with(&e.tag_name)),
+                        "{:?} -> <{}>",
+                        prefix,
+                        e.tag_name
+                    );
+                }
+            }
+        }
+        assert_eq!(previous_len, full_text.len());
+    }
+
+    #[test]
+    fn truncated_inside_constructs() {
+        // Mid multi-byte text: the element keeps the text read so far.
+        let nodes = parse("<div id=\"main\"><p class=\"intro\">Héllo →");
+        let p = &nodes[0].children[0];
+        assert_eq!(element(p).tag_name, "p");
+        assert_eq!(
+            p.children[0].node_type,
+            NodeType::Text("Héllo →".to_string())
+        );
+
+        // Mid comment.
+        let nodes = parse("<div><!-- no");
+        assert_eq!(
+            nodes[0].children[0].node_type,
+            NodeType::Comment(" no".to_string())
+        );
+
+        // Mid quoted attribute value.
+        let nodes = parse("<div><a href=\"/x");
+        let a = element(&nodes[0].children[0]);
+        assert_eq!(a.tag_name, "a");
+        assert_eq!(a.attributes(), &attr_map(&[("href", "/x")]));
+
+        // Mid close tag: the open elements stay open with their children.
+        let nodes = parse("<div><p>x</p");
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(element(&nodes[0].children[0]).tag_name, "p");
+        assert_eq!(text_of(&nodes), "x");
+    }
+
+    #[test]
+    fn inline_svg() {
+        let nodes = parse(
+            "<div><svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 10 10\">\
+             <defs><linearGradient id=\"g\" gradientUnits=\"userSpaceOnUse\">\
+             <stop offset=\"0\"/></linearGradient></defs>\
+             <path d=\"M0 0\"/><path xlink:href=\"#g\" d=\"M1 1\"/></svg>\
+             <p CLASS=x>after</p></div>",
+        );
+        let div = &nodes[0];
+        assert_eq!(div.children.len(), 2);
+
+        let svg = &div.children[0];
+        assert_eq!(
+            element(svg).attributes(),
+            &attr_map(&[
+                ("xmlns", "http://www.w3.org/2000/svg"),
+                ("viewBox", "0 0 10 10")
+            ])
+        );
+        let tags: Vec<&str> = svg
+            .children
+            .iter()
+            .map(|c| element(c).tag_name.as_str())
+            .collect();
+        assert_eq!(tags, ["defs", "path", "path"]);
+
+        let gradient = &svg.children[0].children[0];
+        assert_eq!(element(gradient).tag_name, "linearGradient");
+        assert_eq!(
+            element(gradient).attributes(),
+            &attr_map(&[("id", "g"), ("gradientUnits", "userSpaceOnUse")])
+        );
+        assert_eq!(gradient.children.len(), 1);
+        assert!(gradient.children[0].children.is_empty());
+
+        let (first, second) = (&svg.children[1], &svg.children[2]);
+        assert!(first.children.is_empty() && second.children.is_empty());
+        assert_eq!(
+            element(second).attributes(),
+            &attr_map(&[("xlink:href", "#g"), ("d", "M1 1")])
+        );
+
+        // Back in HTML names are lowercased again.
+        let p = element(&div.children[1]);
+        assert_eq!(p.tag_name, "p");
+        assert_eq!(p.attributes(), &attr_map(&[("class", "x")]));
+    }
+
+    #[test]
+    fn self_closing_only_in_foreign_content() {
+        let nodes = parse("<math><mi/><mo>+</mo></math><div/><span>x</span>");
+        let tags: Vec<&str> = nodes[0]
+            .children
+            .iter()
+            .map(|c| element(c).tag_name.as_str())
+            .collect();
+        assert_eq!(tags, ["mi", "mo"]);
+
+        // Outside foreign content "/>" is ignored, the span ends up inside the div.
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(element(&nodes[1].children[0]).tag_name, "span");
+    }
+
+    fn tags_of(nodes: &[Node]) -> Vec<&str> {
+        nodes
+            .iter()
+            .filter_map(|n| match n.node_type {
+                NodeType::Element(ref e) => Some(e.tag_name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn implied_end_tags() {
+        let nodes = parse("<ul><li>one<li>two<li>three</ul>");
+        assert_eq!(tags_of(&nodes[0].children), ["li", "li", "li"]);
+        assert_eq!(text_of(&nodes[0].children[2..]), "three");
+
+        let nodes = parse("<div><p>one<p>two<div>three</div></div>");
+        assert_eq!(tags_of(&nodes[0].children), ["p", "p", "div"]);
+        assert_eq!(text_of(&nodes[0].children[1..2]), "two");
+
+        let nodes = parse("<table><tr><td>a<td>b<tr><th>c<td>d</table>");
+        let rows = &nodes[0].children;
+        assert_eq!(tags_of(rows), ["tr", "tr"]);
+        assert_eq!(tags_of(&rows[0].children), ["td", "td"]);
+        assert_eq!(tags_of(&rows[1].children), ["th", "td"]);
+
+        let nodes = parse("<dl><dt>term<dd>one<dd>two<dt>next</dl>");
+        assert_eq!(tags_of(&nodes[0].children), ["dt", "dd", "dd", "dt"]);
+
+        let nodes = parse("<select><option>a<option>b<optgroup><option>c</select>");
+        assert_eq!(
+            tags_of(&nodes[0].children),
+            ["option", "option", "optgroup"]
+        );
+        assert_eq!(tags_of(&nodes[0].children[2].children), ["option"]);
+
+        // Inline elements still open are closed along with the element they are in.
+        let nodes = parse("<ul><li><b>one<li>two</ul>");
+        assert_eq!(tags_of(&nodes[0].children), ["li", "li"]);
+        assert_eq!(tags_of(&nodes[0].children[0].children), ["b"]);
+        assert_eq!(text_of(&nodes[0].children[0..1]), "one");
+        assert_eq!(text_of(&nodes[0].children[1..]), "two");
+
+        let nodes = parse("<p>a <b>b<div>c</div>");
+        assert_eq!(tags_of(&nodes), ["p", "div"]);
+        assert_eq!(tags_of(&nodes[0].children), ["b"]);
+        assert_eq!(text_of(&nodes[..1]), "a b");
+        assert_eq!(text_of(&nodes[1..]), "c");
+
+        let nodes = parse("<p>intro <a href=x><i>link</i> more<div>x</div>");
+        assert_eq!(tags_of(&nodes), ["p", "div"]);
+
+        let nodes = parse("<ul><li><p>one<li>two</ul>");
+        assert_eq!(tags_of(&nodes[0].children), ["li", "li"]);
+
+        // A block element in between keeps the new tag nested.
+        let nodes = parse("<ul><li><div><li>inner</li></div></li></ul>");
+        assert_eq!(tags_of(&nodes[0].children), ["li"]);
+        assert_eq!(tags_of(&nodes[0].children[0].children[0].children), ["li"]);
+
+        // Explicit close tags still work and a <p> keeps its inline children.
+        let nodes = parse("<ul><li>one</li><li><b>two</b></li></ul><p>x<b>y</b>z</p>");
+        assert_eq!(tags_of(&nodes), ["ul", "p"]);
+        assert_eq!(tags_of(&nodes[0].children), ["li", "li"]);
+        assert_eq!(text_of(&nodes[1..]), "xyz");
+    }
+
+    #[test]
+    fn preformatted_text() {
+        let art = "  /\\_/\\\n ( o.o )\n  > ^ <\n\n\tend  ";
+        let nodes = parse(&format!("<pre>\n{}</pre>", art));
+        assert_eq!(text_of(&nodes), art);
+
+        // Nested elements inherit it, and textarea keeps its text too.
+        let nodes = parse("<pre><code>  a\n    b</code>\n</pre><textarea>\r\n x  y</textarea>");
+        assert_eq!(text_of(&nodes[0].children[..1]), "  a\n    b");
+        assert_eq!(text_of(&nodes[1..]), " x  y");
+    }
+
+    #[test]
+    fn normal_text_collapses() {
+        let nodes = parse("<p>  a \n\t b  <b> c\n</b>\n</p>");
+        let p = &nodes[0];
+        assert_eq!(p.children[0].node_type, NodeType::Text("a b ".to_string()));
+        assert_eq!(
+            p.children[1].children[0].node_type,
+            NodeType::Text("c ".to_string())
+        );
+        assert_eq!(p.children.len(), 2);
+    }
+
+    #[test]
+    fn normalize_option() {
+        // The nameless close tags are dropped, leaving the text split in three.
+        let html = "<p>Hello, </>world</ >!</p>";
+        let split = parse(html);
+        let texts: Vec<&NodeType> = split[0].children.iter().map(|n| &n.node_type).collect();
+        assert_eq!(
+            texts,
+            [
+                &NodeType::Text("Hello, ".to_string()),
+                &NodeType::Text("world".to_string()),
+                &NodeType::Text("!".to_string())
+            ]
+        );
+
+        let options = ParseOptions { normalize: true };
+        let nodes = HtmlParser::with_options(html, options).parse_nodes();
+        assert_eq!(nodes[0].children.len(), 1);
+        assert_eq!(
+            nodes[0].children[0].node_type,
+            NodeType::Text("Hello, world!".to_string())
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let cases = [
+            ("<!---->", ""),
+            ("<!-- a -->", " a "),
+            ("<!-->", ""),
+            ("<!--->", ""),
+            ("<!--a-b-->", "a-b"),
+            ("<!-- a -- b -->", " a -- b "),
+            ("<!-- a --->", " a -"),
+            ("<!-- a --!>", " a "),
+            ("<!-- a --!-->", " a --!"),
+            ("<!-- <!-- nested --> -->", " <!-- nested "),
+            ("<!-- unterminated", " unterminated"),
+            ("<!-- a -", " a "),
+            ("<!-- a --", " a "),
+            ("<!DOCTYPE html>", "DOCTYPE html"),
+            ("<!-x>", "-x"),
+        ];
+
+        for (html, expected) in cases {
+            let nodes = parse(html);
+            assert_eq!(
+                nodes[0].node_type,
+                NodeType::Comment(expected.to_string()),
+                "{}",
+                html
+            );
+        }
+    }
+
+    #[test]
+    fn attributes() {
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            ("<a>", &[]),
+            ("<a href=\"x\">", &[("href", "x")]),
+            ("<a href='x'>", &[("href", "x")]),
+            ("<td width=100>", &[("width", "100")]),
+            ("<x a = \"b\">", &[("a", "b")]),
+            (
+                "<input value=\"a\"disabled>",
+                &[("value", "a"), ("disabled", "")],
+            ),
+            ("<input disabled>", &[("disabled", "")]),
+            ("<input disabled/>", &[("disabled", "")]),
+            ("<input value=''>", &[("value", "")]),
+            ("<a href=/x/>", &[("href", "/x/")]),
+            (
+                "<a href=/docs/ title=y>",
+                &[("href", "/docs/"), ("title", "y")],
+            ),
+            ("<a href=\"/x/\"/>", &[("href", "/x/")]),
+            ("<a href=x / >", &[("href", "x")]),
+            ("<div ID=a Class=b>", &[("id", "a"), ("class", "b")]),
+            ("<div id=a id=b>", &[("id", "a")]),
+            ("<div data-x=1 DATA-X=2>", &[("data-x", "1")]),
+            (
+                "<input type = \"checkbox\" checked TYPE=radio/>",
+                &[("type", "checkbox"), ("checked", "")],
+            ),
+            ("<a title=\"a > b\">", &[("title", "a > b")]),
+            (
+                "<img alt=\"it's\" src='a.png'>",
+                &[("alt", "it's"), ("src", "a.png")],
+            ),
+            ("<a href=?a=b&c=d>", &[("href", "?a=b&c=d")]),
+        ];
+
+        for &(html, expected) in cases {
+            let nodes = parse(html);
+            assert_eq!(
+                element(&nodes[0]).attributes(),
+                &attr_map(expected),
+                "{}",
+                html
+            );
+        }
+    }
+
+    #[test]
+    fn unquoted_slash_stays_in_value() {
+        let nodes = parse("<p><a href=/docs/>docs</a> after</p>");
+        let p = &nodes[0];
+        assert_eq!(p.children.len(), 2);
+        assert_eq!(element(&p.children[0]).attributes()["href"], "/docs/");
+        assert_eq!(
+            p.children[0].children[0].node_type,
+            NodeType::Text("docs".to_string())
+        );
     }
 }
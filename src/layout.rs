@@ -382,7 +382,7 @@ pub fn layout_tree<'a>(
 fn build_layout_tree<'a>(node: &'a StyledNode) -> LayoutBox<'a> {
     let mut layout_node = LayoutBox::new(
         match node.get_display() {
-            Display::Block => BoxType::Block,
+            Display::Block | Display::ListItem => BoxType::Block,
             Display::Inline => BoxType::Inline,
             Display::InlineBlock => BoxType::InlineBlock,
             Display::None => BoxType::Anonymous,
@@ -393,6 +393,7 @@ fn build_layout_tree<'a>(node: &'a StyledNode) -> LayoutBox<'a> {
     for child in &node.children {
         match child.get_display() {
             Display::Block => layout_node.children.push(build_layout_tree(child)),
+            Display::ListItem => layout_node.children.push(build_layout_tree(child)),
             Display::Inline => layout_node.children.push(build_layout_tree(child)),
             Display::InlineBlock => layout_node.children.push(build_layout_tree(child)),
             Display::None => {}
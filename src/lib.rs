@@ -6,3 +6,4 @@ pub mod html_parse;
 pub mod layout;
 pub mod render;
 pub mod style;
+pub mod url;
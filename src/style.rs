@@ -1,7 +1,10 @@
 use std::collections::HashMap;
+use std::sync::OnceLock;
 use std::{fmt, str};
 
-use crate::css::{Selector, Stylesheet, Value};
+use crate::css::{Color, Declaration, Selector, Stylesheet, Unit, Value};
+use crate::css_parser::CssParser;
+pub use crate::dom::PrintOptions;
 use crate::dom::{ElementData, Node, NodeType};
 
 type PropertyMap<'a> = HashMap<&'a str, &'a Value>;
@@ -16,8 +19,155 @@ pub enum Display {
     Block,
     Inline,
     InlineBlock,
+    ListItem,
     None,
 }
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    PreWrap,
+    NoWrap,
+}
+
+// text-align, Left when absent or unrecognized.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextAlign {
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+// position, Static when absent or unrecognized.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Position {
+    Static,
+    Relative,
+    Absolute,
+    Fixed,
+    Sticky,
+}
+
+// float, None when absent or unrecognized.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Float {
+    None,
+    Left,
+    Right,
+}
+
+// font-weight, Normal when absent or unrecognized. Weight holds numeric values (1 to 1000).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FontWeight {
+    Normal,
+    Bold,
+    Bolder,
+    Lighter,
+    Weight(u16),
+}
+/* Where a rule comes from. Declarations of a later origin always win over an earlier one,
+wherever the rules are in their sheets (author `h1 { font-size: 20px }` beats the UA
+`h1 { font-size: 32px }`).
+*/
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Origin {
+    UserAgent,
+    Author,
+}
+
+// Position of a rule in the cascade: (origin, sheet index, rule index), compared in that order.
+type CascadeOrder = (Origin, usize, usize);
+
+// Default HTML rendering, applied below every author stylesheet by StyledNode::new_with_defaults.
+const USER_AGENT_CSS: &str = "
+html, body, div, p, h1, h2, h3, h4, h5, h6, ul, ol, dl, dt, dd, table, blockquote,
+pre, form, fieldset, header, footer, section, article, aside, nav, main, address,
+figure, figcaption, hr, details, summary, menu, center {
+    display: block;
+}
+head, script, style, title, meta, link, base, template, noscript {
+    display: none;
+}
+li {
+    display: list-item;
+}
+b, strong, th {
+    font-weight: bold;
+}
+i, em, cite, var, dfn, address {
+    font-style: italic;
+}
+pre, code, kbd, samp, tt {
+    font-family: monospace;
+}
+pre, listing, xmp {
+    white-space: pre;
+}
+textarea {
+    white-space: pre-wrap;
+}
+body {
+    margin-top: 8px;
+    margin-right: 8px;
+    margin-bottom: 8px;
+    margin-left: 8px;
+}
+p, ul, ol, dl, blockquote, pre {
+    margin-top: 16px;
+    margin-bottom: 16px;
+}
+h1 {
+    font-size: 32px;
+    font-weight: bold;
+    margin-top: 21px;
+    margin-bottom: 21px;
+}
+h2 {
+    font-size: 24px;
+    font-weight: bold;
+    margin-top: 20px;
+    margin-bottom: 20px;
+}
+h3 {
+    font-size: 19px;
+    font-weight: bold;
+    margin-top: 19px;
+    margin-bottom: 19px;
+}
+h4 {
+    font-size: 16px;
+    font-weight: bold;
+    margin-top: 21px;
+    margin-bottom: 21px;
+}
+h5 {
+    font-size: 13px;
+    font-weight: bold;
+    margin-top: 22px;
+    margin-bottom: 22px;
+}
+h6 {
+    font-size: 11px;
+    font-weight: bold;
+    margin-top: 25px;
+    margin-bottom: 25px;
+}
+ul, ol {
+    padding-left: 40px;
+}
+blockquote {
+    margin-left: 40px;
+    margin-right: 40px;
+}";
+
+// The default stylesheet is parsed on first use and shared afterwards.
+pub fn user_agent_stylesheet() -> &'static Stylesheet {
+    static SHEET: OnceLock<Stylesheet> = OnceLock::new();
+    SHEET.get_or_init(|| CssParser::new(USER_AGENT_CSS.trim()).parse_stylesheet())
+}
+
 /*
 * Will be called for root node only and keep building recursively
 Example HTML
@@ -86,13 +236,49 @@ StyledNode {
 }
 */
 impl<'a> StyledNode<'a> {
+    // Styles the tree with the author stylesheet only.
     pub fn new(node: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+        StyledNode::with_origins(node, &[(Origin::Author, stylesheet)])
+    }
+
+    /* Styles the tree with several author stylesheets. The result is the same as with one
+    stylesheet holding the rules of all of them in order, so later sheets win ties.
+    */
+    pub fn new_multi(node: &'a Node, sheets: &[&'a Stylesheet]) -> StyledNode<'a> {
+        let sheets: Vec<(Origin, &'a Stylesheet)> = sheets
+            .iter()
+            .map(|&sheet| (Origin::Author, sheet))
+            .collect();
+        StyledNode::with_origins(node, &sheets)
+    }
+
+    // Styles the tree with the user agent defaults underneath the author stylesheet.
+    pub fn new_with_defaults(node: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+        StyledNode::new_multi_with_defaults(node, &[stylesheet])
+    }
+
+    // new_multi with the user agent defaults underneath all the author stylesheets.
+    pub fn new_multi_with_defaults(node: &'a Node, sheets: &[&'a Stylesheet]) -> StyledNode<'a> {
+        let mut origins = vec![(Origin::UserAgent, user_agent_stylesheet())];
+        origins.extend(sheets.iter().map(|&sheet| (Origin::Author, sheet)));
+        StyledNode::with_origins(node, &origins)
+    }
+
+    fn with_origins(node: &'a Node, sheets: &[(Origin, &'a Stylesheet)]) -> StyledNode<'a> {
+        let indexed: Vec<(Origin, &'a Stylesheet, StyleIndex)> = sheets
+            .iter()
+            .map(|&(origin, stylesheet)| (origin, stylesheet, StyleIndex::new(stylesheet)))
+            .collect();
+        StyledNode::build(node, &indexed)
+    }
+
+    fn build(node: &'a Node, sheets: &[(Origin, &'a Stylesheet, StyleIndex)]) -> StyledNode<'a> {
         let mut style_children = Vec::new();
 
         for child in &node.children {
             match child.node_type {
                 //Calling itself
-                NodeType::Element(_) => style_children.push(StyledNode::new(&child, stylesheet)),
+                NodeType::Element(_) => style_children.push(StyledNode::build(&child, sheets)),
                 _ => {}
             }
         }
@@ -100,65 +286,452 @@ impl<'a> StyledNode<'a> {
         StyledNode {
             node,
             styles: match node.node_type {
-                NodeType::Element(ref e) => StyledNode::get_styles(e, stylesheet),
+                NodeType::Element(ref e) => StyledNode::get_styles(e, sheets),
                 _ => PropertyMap::new(),
             },
             children: style_children,
         }
     }
 
-    fn get_styles(element: &'a ElementData, stylesheet: &'a Stylesheet) -> PropertyMap<'a> {
-        let mut styles = PropertyMap::new();
+    /* Declarations are applied origin by origin (user agent first, author last) and in source
+    order inside an origin, so a later declaration overwrites an earlier one for the same property.
+    Source order across several sheets is (sheet index, rule index), as if the sheets were one.
+    */
+    fn get_styles(
+        element: &'a ElementData,
+        sheets: &[(Origin, &'a Stylesheet, StyleIndex)],
+    ) -> PropertyMap<'a> {
+        let mut matched: Vec<(CascadeOrder, &'a Declaration)> = Vec::new();
 
-        for rule in &stylesheet.rules {
-            for selector in &rule.selectors {
-                if selector_matches(element, &selector) {
-                    for declar in &rule.declarations {
-                        styles.insert(&declar.property, &declar.value);
-                    }
-                    break;
+        for (sheet_index, &(origin, stylesheet, ref index)) in sheets.iter().enumerate() {
+            for rule_index in matching_rules(element, stylesheet, Some(index)) {
+                for declar in &stylesheet.rules[rule_index].declarations {
+                    matched.push(((origin, sheet_index, rule_index), declar));
                 }
             }
         }
+        // Stable sort, declarations of one rule keep their order.
+        matched.sort_by_key(|&(order, _)| order);
+
+        let mut styles = PropertyMap::new();
+        for (_, declar) in matched {
+            styles.insert(&declar.property, &declar.value);
+        }
         styles
     }
 
+    // The DOM node this styled node was built from.
+    pub fn node(&self) -> &'a Node {
+        self.node
+    }
+
+    // Tag name of the element, None for text and comment nodes.
+    pub fn tag_name(&self) -> Option<&'a str> {
+        match self.node.node_type {
+            NodeType::Element(ref e) => Some(&e.tag_name),
+            _ => None,
+        }
+    }
+
+    // The node itself followed by all styled nodes below it, in pre-order (document order).
+    pub fn descendants(&self) -> StyledDescendants<'_, 'a> {
+        StyledDescendants { stack: vec![self] }
+    }
+
+    /* Styled nodes of the subtree (the node itself included) whose element matches any of the
+    comma separated selectors, in document order. Matching is the same as for stylesheet rules,
+    on the element alone. Combinators are not supported yet, so selector text with one
+    ("div p", "ul > li") matches nothing instead of being read as a list of selectors.
+    Selector text that does not parse into any selector matches nothing either.
+
+    Example
+        root.select("p.note, #header")  ->  [<div, id="header">, <p, class="note">]
+    */
+    pub fn select(&self, selector_text: &str) -> Vec<&StyledNode<'a>> {
+        if has_combinator(selector_text) {
+            return Vec::new();
+        }
+        let selectors = CssParser::new(selector_text).parse_selectors();
+
+        self.descendants()
+            .filter(|styled| match styled.node.node_type {
+                NodeType::Element(ref e) => selectors.iter().any(|s| selector_matches(e, s)),
+                _ => false,
+            })
+            .collect()
+    }
+
     pub fn value(&self, name: &str) -> Option<&&Value> {
         self.styles.get(name)
     }
 
+    // Components of a multi-value property, see Value::components.
+    pub fn values(&self, name: &str) -> Option<&[Value]> {
+        self.value(name).map(|v| v.components())
+    }
+
+    // Keyword value (Value::Other) of a property, None if absent or not a keyword.
+    pub fn keyword(&self, name: &str) -> Option<&str> {
+        keyword_of(self.value(name).copied())
+    }
+
+    // Length value of a property together with its unit, None if absent or not a length.
+    pub fn length(&self, name: &str) -> Option<(f32, Unit)> {
+        length_of(self.value(name).copied())
+    }
+
+    // Color value of a property, None if absent or not a color.
+    pub fn color(&self, name: &str) -> Option<Color> {
+        color_of(self.value(name).copied())
+    }
+
     pub fn get_display(&self) -> Display {
-        match self.value("display") {
-            Some(s) => match **s {
-                Value::Other(ref v) => match v.as_ref() {
-                    "block" => Display::Block,
-                    "none" => Display::None,
-                    "inline-block" => Display::InlineBlock,
-                    _ => Display::Inline,
-                },
-                _ => Display::Inline,
-            },
-            None => Display::Inline,
-        }
+        display_of(self.value("display").copied())
+    }
+
+    pub fn get_white_space(&self) -> WhiteSpace {
+        white_space_of(self.value("white-space").copied())
+    }
+
+    pub fn text_align(&self) -> TextAlign {
+        text_align_of(self.value("text-align").copied())
+    }
+
+    pub fn position(&self) -> Position {
+        position_of(self.value("position").copied())
+    }
+
+    pub fn float_(&self) -> Float {
+        float_of(self.value("float").copied())
+    }
+
+    pub fn font_weight(&self) -> FontWeight {
+        font_weight_of(self.value("font-weight").copied())
     }
 
     pub fn num_or(&self, name: &str, default: f32) -> f32 {
-        match self.value(name) {
-            Some(v) => match **v {
-                Value::Length(n, _) => n,
-                _ => default,
+        num_of(self.value(name).copied(), default)
+    }
+
+    /* Copies the styled tree into a StyledTree that owns its node data and values,
+    so the DOM and the stylesheets can be dropped or changed afterwards.
+    */
+    pub fn to_owned_tree(&self) -> StyledTree {
+        StyledTree {
+            node_type: self.node.node_type.clone(),
+            style: ComputedStyle {
+                properties: self
+                    .styles
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), (*value).clone()))
+                    .collect(),
             },
-            None => default,
+            children: self.children.iter().map(|c| c.to_owned_tree()).collect(),
         }
     }
 }
 
+// Owned version of the computed properties of one node.
+#[derive(Clone, PartialEq, Default)]
+pub struct ComputedStyle {
+    pub properties: HashMap<String, Value>,
+}
+
+// Owned version of StyledNode, does not borrow the DOM or the stylesheets.
+#[derive(Clone, PartialEq)]
+pub struct StyledTree {
+    pub node_type: NodeType,
+    pub style: ComputedStyle,
+    pub children: Vec<StyledTree>,
+}
+
+impl ComputedStyle {
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.properties.get(name)
+    }
+
+    pub fn values(&self, name: &str) -> Option<&[Value]> {
+        self.value(name).map(|v| v.components())
+    }
+
+    pub fn keyword(&self, name: &str) -> Option<&str> {
+        keyword_of(self.value(name))
+    }
+
+    pub fn length(&self, name: &str) -> Option<(f32, Unit)> {
+        length_of(self.value(name))
+    }
+
+    pub fn color(&self, name: &str) -> Option<Color> {
+        color_of(self.value(name))
+    }
+
+    pub fn get_display(&self) -> Display {
+        display_of(self.value("display"))
+    }
+
+    pub fn get_white_space(&self) -> WhiteSpace {
+        white_space_of(self.value("white-space"))
+    }
+
+    pub fn text_align(&self) -> TextAlign {
+        text_align_of(self.value("text-align"))
+    }
+
+    pub fn position(&self) -> Position {
+        position_of(self.value("position"))
+    }
+
+    pub fn float_(&self) -> Float {
+        float_of(self.value("float"))
+    }
+
+    pub fn font_weight(&self) -> FontWeight {
+        font_weight_of(self.value("font-weight"))
+    }
+
+    pub fn num_or(&self, name: &str, default: f32) -> f32 {
+        num_of(self.value(name), default)
+    }
+}
+
+impl fmt::Debug for ComputedStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.properties)
+    }
+}
+
+impl fmt::Debug for StyledTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {:?}", self.node_type, self.style)
+    }
+}
+
+// Shared by StyledNode and ComputedStyle so both read values the same way.
+fn keyword_of(value: Option<&Value>) -> Option<&str> {
+    match value {
+        Some(Value::Other(ref v)) => Some(v),
+        _ => None,
+    }
+}
+
+fn length_of(value: Option<&Value>) -> Option<(f32, Unit)> {
+    match value {
+        Some(&Value::Length(n, unit)) => Some((n, unit)),
+        _ => None,
+    }
+}
+
+fn color_of(value: Option<&Value>) -> Option<Color> {
+    match value {
+        Some(Value::Color(ref c)) => Some(c.clone()),
+        _ => None,
+    }
+}
+
+fn display_of(value: Option<&Value>) -> Display {
+    match keyword_of(value) {
+        Some("block") => Display::Block,
+        Some("none") => Display::None,
+        Some("inline-block") => Display::InlineBlock,
+        Some("list-item") => Display::ListItem,
+        _ => Display::Inline,
+    }
+}
+
+fn white_space_of(value: Option<&Value>) -> WhiteSpace {
+    match keyword_of(value) {
+        Some("pre") => WhiteSpace::Pre,
+        Some("pre-wrap") => WhiteSpace::PreWrap,
+        Some("nowrap") => WhiteSpace::NoWrap,
+        _ => WhiteSpace::Normal,
+    }
+}
+
+fn text_align_of(value: Option<&Value>) -> TextAlign {
+    match keyword_of(value) {
+        Some("right") => TextAlign::Right,
+        Some("center") => TextAlign::Center,
+        Some("justify") => TextAlign::Justify,
+        _ => TextAlign::Left,
+    }
+}
+
+fn position_of(value: Option<&Value>) -> Position {
+    match keyword_of(value) {
+        Some("relative") => Position::Relative,
+        Some("absolute") => Position::Absolute,
+        Some("fixed") => Position::Fixed,
+        Some("sticky") => Position::Sticky,
+        _ => Position::Static,
+    }
+}
+
+fn float_of(value: Option<&Value>) -> Float {
+    match keyword_of(value) {
+        Some("left") => Float::Left,
+        Some("right") => Float::Right,
+        _ => Float::None,
+    }
+}
+
+// "normal" and "bold" map to their keywords, not to Weight(400) / Weight(700).
+fn font_weight_of(value: Option<&Value>) -> FontWeight {
+    match keyword_of(value) {
+        Some("bold") => FontWeight::Bold,
+        Some("bolder") => FontWeight::Bolder,
+        Some("lighter") => FontWeight::Lighter,
+        Some(v) => match v.parse::<u16>() {
+            Ok(n) if (1..=1000).contains(&n) => FontWeight::Weight(n),
+            _ => FontWeight::Normal,
+        },
+        None => FontWeight::Normal,
+    }
+}
+
+fn num_of(value: Option<&Value>, default: f32) -> f32 {
+    value.and_then(first_length).unwrap_or(default)
+}
+
+// For lists the first length found (depth first) is used, `margin: auto 10px` -> 10.
+fn first_length(value: &Value) -> Option<f32> {
+    match *value {
+        Value::Length(n, _) => Some(n),
+        Value::List(ref l) | Value::CommaList(ref l) => l.iter().find_map(first_length),
+        _ => None,
+    }
+}
+
+pub struct StyledDescendants<'b, 'a> {
+    stack: Vec<&'b StyledNode<'a>>,
+}
+
+impl<'b, 'a> Iterator for StyledDescendants<'b, 'a> {
+    type Item = &'b StyledNode<'a>;
+
+    fn next(&mut self) -> Option<&'b StyledNode<'a>> {
+        let node = self.stack.pop()?;
+        // Pushed in reverse so the first child comes out next.
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
+// Whether one of the comma separated selectors combines several compound selectors.
+fn has_combinator(selector_text: &str) -> bool {
+    selector_text.split(',').any(|selector| {
+        selector
+            .trim()
+            .contains(|c: char| c.is_whitespace() || c == '>' || c == '+' || c == '~')
+    })
+}
+
+/* Computed properties of the first node (in document order) matching selector,
+None if nothing matches.
+    styles_of(&root, "#header")  ->  Some({"display": "block", "height": 40.0})
+*/
+pub fn styles_of<'a>(root: &StyledNode<'a>, selector: &str) -> Option<HashMap<&'a str, &'a Value>> {
+    root.select(selector)
+        .first()
+        .map(|styled| styled.styles.clone())
+}
+
 impl<'a> fmt::Debug for StyledNode<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}: {:?}", self.node, self.styles)
     }
 }
 
+/* Positions of the rules of one stylesheet, bucketed by the most selective part of each
+simple selector: its id, else its first class, else its tag name. Selectors with none of them
+(like `*`) go to universal. An element can then only match rules found in its own id, class and
+tag buckets, so only those are checked with selector_matches instead of every rule in the sheet.
+*/
+pub struct StyleIndex {
+    by_id: HashMap<String, Vec<usize>>,
+    by_class: HashMap<String, Vec<usize>>,
+    by_tag: HashMap<String, Vec<usize>>,
+    universal: Vec<usize>,
+}
+
+impl StyleIndex {
+    pub fn new(stylesheet: &Stylesheet) -> StyleIndex {
+        let mut index = StyleIndex {
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_tag: HashMap::new(),
+            universal: Vec::new(),
+        };
+
+        for (rule_index, rule) in stylesheet.rules.iter().enumerate() {
+            for selector in &rule.selectors {
+                for simple in &selector.simple {
+                    let bucket = if let Some(ref id) = simple.id {
+                        index.by_id.entry(id.clone()).or_default()
+                    } else if let Some(class) = simple.classes.first() {
+                        index.by_class.entry(class.clone()).or_default()
+                    } else if let Some(ref tag) = simple.tag_name {
+                        index.by_tag.entry(tag.clone()).or_default()
+                    } else {
+                        &mut index.universal
+                    };
+                    // A rule is added once per bucket even if several of its selectors land there.
+                    if bucket.last() != Some(&rule_index) {
+                        bucket.push(rule_index);
+                    }
+                }
+            }
+        }
+        index
+    }
+
+    // Rules that may match the element, in source order and without duplicates.
+    pub fn candidates(&self, element: &ElementData) -> Vec<usize> {
+        let mut candidates = self.universal.clone();
+
+        if let Some(rules) = element.get_id().and_then(|id| self.by_id.get(id)) {
+            candidates.extend(rules);
+        }
+        for class in element.classes() {
+            if let Some(rules) = self.by_class.get(class) {
+                candidates.extend(rules);
+            }
+        }
+        if let Some(rules) = self.by_tag.get(&element.tag_name) {
+            candidates.extend(rules);
+        }
+
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+}
+
+/* Positions of the rules in stylesheet with a selector matching element, in source order.
+With an index only the index candidates are checked, without one every rule is (brute force).
+Both give the same result, the brute force path is kept to check the index against.
+*/
+pub fn matching_rules(
+    element: &ElementData,
+    stylesheet: &Stylesheet,
+    index: Option<&StyleIndex>,
+) -> Vec<usize> {
+    let candidates = match index {
+        Some(index) => index.candidates(element),
+        None => (0..stylesheet.rules.len()).collect(),
+    };
+
+    candidates
+        .into_iter()
+        .filter(|&rule_index| {
+            stylesheet.rules[rule_index]
+                .selectors
+                .iter()
+                .any(|selector| selector_matches(element, selector))
+        })
+        .collect()
+}
+
 //Not matching all selectors but only selector that is in current rule
 fn selector_matches(element: &ElementData, selector: &Selector) -> bool {
     for simple in &selector.simple {
@@ -189,10 +762,8 @@ fn selector_matches(element: &ElementData, selector: &Selector) -> bool {
                 _ => {}
             },
         }
-        let element_classes = element.get_classes();
-
         for class in &simple.classes {
-            selector_match &= element_classes.contains::<str>(class);
+            selector_match &= element.has_class(class);
         }
 
         if selector_match {
@@ -202,11 +773,557 @@ fn selector_matches(element: &ElementData, selector: &Selector) -> bool {
     false
 }
 
+/* Writes node and its descendants to out, one node per line, children indented below
+their parent. Walks the tree with an explicit stack so deep trees can not overflow it.
+
+    Example (default options)
+    <div, class="a">: {"display": "block"}
+      <p,>: {"color": r: 1 g: 0 b: 0 a: 1, "width": 10.0}
+*/
+pub fn write_tree<W: fmt::Write>(
+    node: &StyledNode,
+    out: &mut W,
+    opts: &PrintOptions,
+) -> fmt::Result {
+    let mut stack = vec![(node, 0)];
+
+    while let Some((node, depth)) = stack.pop() {
+        if matches!(opts.max_depth, Some(max) if depth > max) {
+            continue;
+        }
+        if !opts.include_text && !matches!(node.node.node_type, NodeType::Element(_)) {
+            continue;
+        }
+
+        let indent = " ".repeat(depth * opts.indent);
+        write!(out, "{}{:?}", indent, node.node)?;
+        if opts.show_properties {
+            write!(out, ": ")?;
+            write_properties(out, &node.styles)?;
+        }
+        writeln!(out)?;
+
+        // Reversed so the first child is popped first.
+        for child in node.children.iter().rev() {
+            stack.push((child, depth + 1));
+        }
+    }
+    Ok(())
+}
+
+// Same format as the Debug output of the map but in a stable order.
+fn write_properties<W: fmt::Write>(out: &mut W, styles: &PropertyMap) -> fmt::Result {
+    let mut names: Vec<&&str> = styles.keys().collect();
+    names.sort();
+
+    write!(out, "{{")?;
+    for (i, name) in names.into_iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write!(out, "{:?}: {:?}", name, styles[name])?;
+    }
+    write!(out, "}}")
+}
+
+impl<'a> fmt::Display for StyledNode<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_tree(self, f, &PrintOptions::default())
+    }
+}
+
+// Prints the tree to stdout, every line indented by indent_size more spaces.
 pub fn pretty_print(node: &StyledNode, indent_size: usize) {
     let indent = (0..indent_size).map(|_| " ").collect::<String>();
-    println!("{}{:?}", indent, node);
+    for line in node.to_string().lines() {
+        println!("{}{}", indent, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::{Rule, SimpleSelector};
+    use crate::dom::AttrMap;
+    use crate::html_parse::HtmlParser;
+
+    // Small deterministic generator (LCG) so the generated cases are the same on every run.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 33) % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    const TAGS: [&str; 4] = ["div", "p", "span", "a"];
+    const IDS: [&str; 4] = ["main", "nav", "side", "foot"];
+    const CLASSES: [&str; 6] = ["a", "b", "c", "d", "e", "f"];
+
+    fn random_simple(rng: &mut Lcg) -> SimpleSelector {
+        // 0: universal (no tag, id or class), 1: tag, 2: id, 3: classes, 4: everything.
+        let kind = rng.below(5);
+        let tag_name = match kind {
+            1 | 4 => Some(rng.pick(&TAGS).to_string()),
+            _ => None,
+        };
+        let id = match kind {
+            2 | 4 => Some(rng.pick(&IDS).to_string()),
+            _ => None,
+        };
+        let classes = match kind {
+            3 | 4 => (0..1 + rng.below(3))
+                .map(|_| rng.pick(&CLASSES).to_string())
+                .collect(),
+            _ => Vec::new(),
+        };
+        SimpleSelector::new(tag_name, id, classes)
+    }
+
+    fn random_stylesheet(rng: &mut Lcg) -> Stylesheet {
+        let rules = (0..150)
+            .map(|_| {
+                let selectors = (0..1 + rng.below(3))
+                    .map(|_| {
+                        let simple = (0..1 + rng.below(2)).map(|_| random_simple(rng)).collect();
+                        Selector::new(simple, Vec::new())
+                    })
+                    .collect();
+                Rule::new(selectors, Vec::new())
+            })
+            .collect();
+        Stylesheet::new(rules)
+    }
 
-    for child in node.children.iter() {
-        pretty_print(&child, indent_size + 2);
+    fn random_element(rng: &mut Lcg) -> ElementData {
+        let mut attributes = AttrMap::new();
+        if rng.below(3) == 0 {
+            attributes.insert("id".to_string(), rng.pick(&IDS).to_string());
+        }
+        let classes: Vec<&str> = (0..rng.below(5)).map(|_| rng.pick(&CLASSES)).collect();
+        attributes.insert("class".to_string(), classes.join(" "));
+        ElementData::new(rng.pick(&TAGS).to_string(), attributes)
+    }
+
+    fn parse_html(html: &str) -> Node {
+        HtmlParser::new(html).parse_nodes().remove(0)
+    }
+
+    #[test]
+    fn user_agent_defaults() {
+        let root = parse_html("<body><div>a</div><span>b</span><h1>c</h1><head></head></body>");
+        let author = Stylesheet::default();
+        let styled = StyledNode::new_with_defaults(&root, &author);
+        let [div, span, h1, head] = &styled.children[..] else {
+            panic!("expected four children");
+        };
+
+        assert!(matches!(styled.get_display(), Display::Block));
+        assert!(matches!(div.get_display(), Display::Block));
+        assert!(matches!(span.get_display(), Display::Inline));
+        assert!(matches!(head.get_display(), Display::None));
+        assert_eq!(h1.length("font-size"), Some((32.0, Unit::Px)));
+        assert_eq!(h1.font_weight(), FontWeight::Bold);
+        assert_eq!(styled.num_or("margin-top", 0.0), 8.0);
+    }
+
+    fn computed(properties: &[(&str, Value)]) -> ComputedStyle {
+        ComputedStyle {
+            properties: properties
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    fn keyword(k: &str) -> Value {
+        Value::Other(k.to_string())
+    }
+
+    #[test]
+    fn value_getters() {
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        let style = computed(&[
+            ("display", keyword("block")),
+            ("width", Value::Length(10.0, Unit::Em)),
+            ("color", Value::Color(red.clone())),
+        ]);
+
+        // Present with the right type.
+        assert_eq!(style.keyword("display"), Some("block"));
+        assert_eq!(style.length("width"), Some((10.0, Unit::Em)));
+        assert_eq!(style.color("color"), Some(red));
+        // Present with another type.
+        assert_eq!(style.keyword("width"), None);
+        assert_eq!(style.length("color"), None);
+        assert_eq!(style.color("display"), None);
+        // Absent.
+        assert_eq!(style.keyword("float"), None);
+        assert_eq!(style.length("height"), None);
+        assert_eq!(style.color("background-color"), None);
+    }
+
+    #[test]
+    fn enum_getters() {
+        let style = computed(&[
+            ("text-align", keyword("center")),
+            ("position", keyword("absolute")),
+            ("float", keyword("right")),
+            ("font-weight", keyword("600")),
+        ]);
+        assert_eq!(style.text_align(), TextAlign::Center);
+        assert_eq!(style.position(), Position::Absolute);
+        assert_eq!(style.float_(), Float::Right);
+        assert_eq!(style.font_weight(), FontWeight::Weight(600));
+
+        // Absent properties give the initial value.
+        let empty = computed(&[]);
+        assert_eq!(empty.text_align(), TextAlign::Left);
+        assert_eq!(empty.position(), Position::Static);
+        assert_eq!(empty.float_(), Float::None);
+        assert_eq!(empty.font_weight(), FontWeight::Normal);
+
+        // So do unknown keywords and values of the wrong type.
+        let unknown = computed(&[
+            ("text-align", keyword("middle")),
+            ("position", Value::Length(1.0, Unit::Px)),
+            ("float", Value::Color(Color::default())),
+            ("font-weight", keyword("1001")),
+        ]);
+        assert_eq!(unknown.text_align(), TextAlign::Left);
+        assert_eq!(unknown.position(), Position::Static);
+        assert_eq!(unknown.float_(), Float::None);
+        assert_eq!(unknown.font_weight(), FontWeight::Normal);
+
+        let weights = [
+            ("bold", FontWeight::Bold),
+            ("bolder", FontWeight::Bolder),
+            ("lighter", FontWeight::Lighter),
+            ("normal", FontWeight::Normal),
+            ("1", FontWeight::Weight(1)),
+            ("0", FontWeight::Normal),
+        ];
+        for (weight, expected) in weights {
+            let style = computed(&[("font-weight", keyword(weight))]);
+            assert_eq!(style.font_weight(), expected, "{}", weight);
+        }
+    }
+
+    #[test]
+    fn styled_node_getters() {
+        let root = parse_html("<div><p>a</p></div>");
+        let sheet = CssParser::new(
+            "div { text-align: justify; position: sticky; float: left; font-weight: bold; } \
+             p { width: 50%; color: #00ff00; display: inline-block; }",
+        )
+        .parse_stylesheet();
+        let styled = StyledNode::new(&root, &sheet);
+        let p = &styled.children[0];
+
+        assert_eq!(styled.text_align(), TextAlign::Justify);
+        assert_eq!(styled.position(), Position::Sticky);
+        assert_eq!(styled.float_(), Float::Left);
+        assert_eq!(styled.font_weight(), FontWeight::Bold);
+        assert_eq!(p.length("width"), Some((50.0, Unit::Pct)));
+        assert_eq!(p.color("color"), Some(Color::new(0.0, 1.0, 0.0, 1.0)));
+        assert_eq!(p.keyword("display"), Some("inline-block"));
+        // Not inherited: p has none of the div properties.
+        assert_eq!(p.text_align(), TextAlign::Left);
+        assert_eq!(p.keyword("position"), None);
+    }
+
+    #[test]
+    fn later_stylesheet_wins() {
+        let root = parse_html("<div><p class=\"a\">x</p><span>y</span></div>");
+        let first = CssParser::new("p { color: #ff0000; width: 10px; } span { color: #ff0000; }")
+            .parse_stylesheet();
+        let second = CssParser::new("p { color: #0000ff; }").parse_stylesheet();
+        let red = Some(Color::new(1.0, 0.0, 0.0, 1.0));
+        let blue = Some(Color::new(0.0, 0.0, 1.0, 1.0));
+
+        let styled = StyledNode::new_multi(&root, &[&first, &second]);
+        let (p, span) = (&styled.children[0], &styled.children[1]);
+        assert_eq!(p.color("color"), blue);
+        assert_eq!(p.length("width"), Some((10.0, Unit::Px)));
+        assert_eq!(span.color("color"), red);
+
+        let styled = StyledNode::new_multi(&root, &[&second, &first]);
+        assert_eq!(styled.children[0].color("color"), red);
+
+        // Same result as one sheet holding both in order.
+        let mut merged =
+            CssParser::new("p { color: #ff0000; width: 10px; } span { color: #ff0000; }")
+                .parse_stylesheet();
+        merged.merge(CssParser::new("p { color: #0000ff; }").parse_stylesheet());
+        let single = StyledNode::new(&root, &merged);
+        let multi = StyledNode::new_multi(&root, &[&first, &second]);
+        assert_eq!(single.to_owned_tree(), multi.to_owned_tree());
+    }
+
+    #[test]
+    fn multiple_stylesheets_with_defaults() {
+        let root = parse_html("<div><p>x</p><span>y</span></div>");
+        let first = CssParser::new("span { display: block; }").parse_stylesheet();
+        let second =
+            CssParser::new("p { display: inline; } span { display: none; }").parse_stylesheet();
+
+        let styled = StyledNode::new_multi_with_defaults(&root, &[&first, &second]);
+        assert!(matches!(styled.get_display(), Display::Block));
+        assert!(matches!(styled.children[0].get_display(), Display::Inline));
+        assert!(matches!(styled.children[1].get_display(), Display::None));
+        assert_eq!(styled.children[0].num_or("margin-top", 0.0), 16.0);
+    }
+
+    #[test]
+    fn class_selectors() {
+        let root = parse_html(
+            "<div><p class=\" a  b\tc \">x</p><p class=\"ab\">y</p><p class=\"B\">z</p>\
+             <p class=\"\">w</p></div>",
+        );
+        let sheet = CssParser::new(".b { width: 1px; } .c.a { height: 2px; } .B { float: left; }")
+            .parse_stylesheet();
+        let styled = StyledNode::new(&root, &sheet);
+        let [abc, ab, upper, empty] = &styled.children[..] else {
+            panic!("expected four children");
+        };
+
+        assert_eq!(abc.length("width"), Some((1.0, Unit::Px)));
+        assert_eq!(abc.length("height"), Some((2.0, Unit::Px)));
+        assert_eq!(abc.float_(), Float::None);
+        assert_eq!(ab.length("width"), None);
+        assert_eq!(upper.length("width"), None);
+        assert_eq!(upper.float_(), Float::Left);
+        assert!(empty.value("width").is_none() && empty.value("float").is_none());
+    }
+
+    const PRINT_DOC: &str = "<div id=\"main\"><p class=\"note\">a<b>b</b></p><p>c</p></div>";
+    const PRINT_CSS: &str = "#main { width: 100px; display: block; } \
+        .note { color: #ff0000; } b { font-weight: bold; }";
+
+    #[test]
+    fn write_tree_default() {
+        let root = parse_html(PRINT_DOC);
+        let sheet = CssParser::new(PRINT_CSS).parse_stylesheet();
+        let styled = StyledNode::new(&root, &sheet);
+
+        let expected = "\
+<div, id=\"main\">: {\"display\": \"block\", \"width\": 100.0}
+  <p, class=\"note\">: {\"color\": r: 1 g: 0 b: 0 a: 1}
+    <b,>: {\"font-weight\": \"bold\"}
+  <p,>: {}
+";
+        assert_eq!(styled.to_string(), expected);
+        assert_eq!(format!("{}", styled), expected);
+    }
+
+    #[test]
+    fn write_tree_options() {
+        let root = parse_html(PRINT_DOC);
+        let sheet = CssParser::new(PRINT_CSS).parse_stylesheet();
+        let styled = StyledNode::new(&root, &sheet);
+
+        let options = PrintOptions {
+            indent: 4,
+            show_properties: false,
+            max_depth: Some(1),
+            ..PrintOptions::default()
+        };
+        let mut out = String::new();
+        write_tree(&styled, &mut out, &options).unwrap();
+        assert_eq!(
+            out,
+            "<div, id=\"main\">\n    <p, class=\"note\">\n    <p,>\n"
+        );
+
+        let options = PrintOptions {
+            max_depth: Some(0),
+            ..PrintOptions::default()
+        };
+        let mut out = String::new();
+        write_tree(&styled.children[0], &mut out, &options).unwrap();
+        assert_eq!(
+            out,
+            "<p, class=\"note\">: {\"color\": r: 1 g: 0 b: 0 a: 1}\n"
+        );
+    }
+
+    #[test]
+    fn write_tree_sorts_attributes() {
+        let sheet = CssParser::new("a { color: #ff0000; display: inline; }").parse_stylesheet();
+        for _ in 0..8 {
+            let root = parse_html("<a id=\"y\" href=\"x\" title=\"t\" class=\"z\">link</a>");
+            let styled = StyledNode::new(&root, &sheet);
+            assert_eq!(
+                styled.to_string(),
+                "<a, class=\"z\" href=\"x\" id=\"y\" title=\"t\">: \
+                 {\"color\": r: 1 g: 0 b: 0 a: 1, \"display\": \"inline\"}\n"
+            );
+        }
+    }
+
+    #[test]
+    fn white_space() {
+        let root = parse_html(
+            "<div><pre>a</pre><p>b</p><code>c</code><textarea>d</textarea><kbd>e</kbd></div>",
+        );
+        let author = CssParser::new("p { white-space: nowrap; }").parse_stylesheet();
+        let styled = StyledNode::new_with_defaults(&root, &author);
+        let modes: Vec<WhiteSpace> = styled
+            .children
+            .iter()
+            .map(|c| c.get_white_space())
+            .collect();
+
+        assert_eq!(styled.get_white_space(), WhiteSpace::Normal);
+        // Inline code only gets a monospace font, its text was collapsed by the parser.
+        assert_eq!(
+            modes,
+            [
+                WhiteSpace::Pre,
+                WhiteSpace::NoWrap,
+                WhiteSpace::Normal,
+                WhiteSpace::PreWrap,
+                WhiteSpace::Normal
+            ]
+        );
+        assert_eq!(styled.children[2].keyword("font-family"), Some("monospace"));
+    }
+
+    #[test]
+    fn author_beats_user_agent() {
+        let root = parse_html("<div><h1>a</h1><p>b</p></div>");
+        let author =
+            CssParser::new("h1 { font-size: 20px; } p { display: inline; }").parse_stylesheet();
+        let styled = StyledNode::new_with_defaults(&root, &author);
+
+        assert_eq!(
+            styled.children[0].length("font-size"),
+            Some((20.0, Unit::Px))
+        );
+        assert!(matches!(styled.children[1].get_display(), Display::Inline));
+        // Without the defaults only the author rules apply.
+        let styled = StyledNode::new(&root, &author);
+        assert!(matches!(styled.get_display(), Display::Inline));
+    }
+
+    #[test]
+    fn select_and_styles_of() {
+        let root = parse_html(
+            "<div id=\"header\"><p class=\"note\">a</p><p class=\"hidden\">b</p>\
+             <span class=\"note x\">c</span></div>",
+        );
+        let sheet = CssParser::new(
+            "#header { display: block; height: 40px; } .note { color: #ff0000; } \
+             .hidden { display: none; }",
+        )
+        .parse_stylesheet();
+        let styled = StyledNode::new(&root, &sheet);
+
+        let tags = |nodes: Vec<&StyledNode>| -> Vec<String> {
+            nodes.iter().map(|n| format!("{:?}", n.node())).collect()
+        };
+        assert_eq!(
+            tags(styled.select("p.note, #header")),
+            ["<div, id=\"header\">", "<p, class=\"note\">"]
+        );
+        let notes: Vec<Option<&str>> = styled
+            .select(".note")
+            .iter()
+            .map(|n| n.tag_name())
+            .collect();
+        assert_eq!(notes, [Some("p"), Some("span")]);
+        let hidden: Vec<&StyledNode> = styled
+            .descendants()
+            .filter(|n| matches!(n.get_display(), Display::None))
+            .collect();
+        assert_eq!(tags(hidden), ["<p, class=\"hidden\">"]);
+
+        let header = styles_of(&styled, "#header").unwrap();
+        assert_eq!(header.len(), 2);
+        assert_eq!(
+            length_of(header.get("height").copied()),
+            Some((40.0, Unit::Px))
+        );
+        let note = styles_of(&styled, "span.note").unwrap();
+        assert_eq!(
+            color_of(note.get("color").copied()),
+            Some(Color::new(1.0, 0.0, 0.0, 1.0))
+        );
+        assert!(styles_of(&styled, ".missing").is_none());
+    }
+
+    #[test]
+    fn select_rejects_combinators() {
+        let root = parse_html("<div><p>a</p><ul><li>b</li></ul></div>");
+        let sheet = Stylesheet::default();
+        let styled = StyledNode::new(&root, &sheet);
+
+        assert_eq!(styled.select("p").len(), 1);
+        assert_eq!(styled.select(" p , li ").len(), 2);
+        for text in ["div p", "ul > li", "ul>li", "p + ul", "p ~ ul", "p, div li"] {
+            assert!(styled.select(text).is_empty(), "{}", text);
+        }
+        assert!(styled.select("").is_empty());
+    }
+
+    #[test]
+    fn index_matches_brute_force() {
+        let mut rng = Lcg(42);
+
+        for _ in 0..20 {
+            let sheet = random_stylesheet(&mut rng);
+            let index = StyleIndex::new(&sheet);
+
+            for _ in 0..300 {
+                let element = random_element(&mut rng);
+                assert_eq!(
+                    matching_rules(&element, &sheet, Some(&index)),
+                    matching_rules(&element, &sheet, None),
+                    "{:?}",
+                    element
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn owned_tree_outlives_dom_and_stylesheet() {
+        fn assert_send<T: Send>() {}
+        assert_send::<StyledTree>();
+
+        let tree = {
+            let root = parse_html("<div><p>text</p><span></span></div>");
+            let sheet =
+                CssParser::new("div { display: block; width: 50px; } p { margin: 1px 2px; }")
+                    .parse_stylesheet();
+            StyledNode::new(&root, &sheet).to_owned_tree()
+        };
+
+        // The DOM and the stylesheet are gone, the tree can still be read, on another thread too.
+        let tree = std::thread::spawn(move || tree).join().unwrap();
+        assert!(matches!(tree.style.get_display(), Display::Block));
+        assert_eq!(tree.style.num_or("width", 0.0), 50.0);
+        assert_eq!(tree.style.num_or("height", 7.0), 7.0);
+        assert_eq!(tree.children.len(), 2);
+        match tree.children[0].node_type {
+            NodeType::Element(ref e) => assert_eq!(e.tag_name, "p"),
+            _ => panic!("expected <p>"),
+        }
+        assert_eq!(
+            tree.children[0].style.value("margin"),
+            Some(&Value::List(vec![
+                Value::Length(1.0, Unit::Px),
+                Value::Length(2.0, Unit::Px)
+            ]))
+        );
+        assert!(tree.children[1].style.value("display").is_none());
     }
 }
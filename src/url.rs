@@ -0,0 +1,227 @@
+/* Resolution of relative references against a base URL (RFC 3986, section 5.2).
+
+    Example
+    base: https://example.com/blog/post.html
+
+    "../css/site.css"      -> https://example.com/css/site.css
+    "img/a.png"            -> https://example.com/blog/img/a.png
+    "//cdn.example.com/x"  -> https://cdn.example.com/x
+    "?page=2"              -> https://example.com/blog/post.html?page=2
+    "#top"                 -> https://example.com/blog/post.html#top
+    ""                     -> https://example.com/blog/post.html
+*/
+
+// The five components of a URL reference, each None when absent (an empty query "?" is Some("")).
+struct UrlParts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+/* Resolves reference against base. A reference that already has a scheme is returned
+unchanged. Leading and trailing whitespace of the reference is ignored.
+*/
+pub fn resolve(base: &str, reference: &str) -> String {
+    let reference = reference.trim();
+    let r = split(reference);
+    if r.scheme.is_some() {
+        return reference.to_string();
+    }
+    let b = split(base.trim());
+
+    let (authority, path, query) = if r.authority.is_some() {
+        (r.authority, remove_dot_segments(r.path), r.query)
+    } else if r.path.is_empty() {
+        (b.authority, b.path.to_string(), r.query.or(b.query))
+    } else if r.path.starts_with('/') {
+        (b.authority, remove_dot_segments(r.path), r.query)
+    } else {
+        (
+            b.authority,
+            remove_dot_segments(&merge(&b, r.path)),
+            r.query,
+        )
+    };
+
+    let mut result = String::new();
+    if let Some(scheme) = b.scheme {
+        result.push_str(scheme);
+        result.push(':');
+    }
+    if let Some(authority) = authority {
+        result.push_str("//");
+        result.push_str(authority);
+    }
+    result.push_str(&path);
+    if let Some(query) = query {
+        result.push('?');
+        result.push_str(query);
+    }
+    if let Some(fragment) = r.fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+fn split(url: &str) -> UrlParts<'_> {
+    let (rest, fragment) = match url.find('#') {
+        Some(i) => (&url[..i], Some(&url[i + 1..])),
+        None => (url, None),
+    };
+    let (rest, query) = match rest.find('?') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+
+    // A scheme is letters, digits, '+', '-', '.' before the first ':' and must start with a letter.
+    let (scheme, rest) = match rest.find(':') {
+        Some(i)
+            if rest[..i].starts_with(|c: char| c.is_ascii_alphabetic())
+                && rest[..i]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.') =>
+        {
+            (Some(&rest[..i]), &rest[i + 1..])
+        }
+        _ => (None, rest),
+    };
+
+    let (authority, path) = match rest.strip_prefix("//") {
+        Some(after) => {
+            let end = after.find('/').unwrap_or(after.len());
+            (Some(&after[..end]), &after[end..])
+        }
+        None => (None, rest),
+    };
+
+    UrlParts {
+        scheme,
+        authority,
+        path,
+        query,
+        fragment,
+    }
+}
+
+// Puts a relative path next to the last segment of the base path ("/blog/post.html" + "a.png").
+fn merge(base: &UrlParts, path: &str) -> String {
+    if base.authority.is_some() && base.path.is_empty() {
+        return format!("/{}", path);
+    }
+    match base.path.rfind('/') {
+        Some(i) => format!("{}{}", &base.path[..=i], path),
+        None => path.to_string(),
+    }
+}
+
+/* Removes "." and ".." segments. ".." above the root is dropped instead of failing,
+so "/../../a" becomes "/a".
+*/
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let absolute = path.starts_with('/');
+    let segments: Vec<&str> = path.split('/').skip(if absolute { 1 } else { 0 }).collect();
+
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        match *segment {
+            "." => {
+                if last {
+                    output.push("");
+                }
+            }
+            ".." => {
+                output.pop();
+                if last {
+                    output.push("");
+                }
+            }
+            s => output.push(s),
+        }
+    }
+
+    let joined = output.join("/");
+    if absolute {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3986_examples() {
+        // RFC 3986, sections 5.4.1 and 5.4.2.
+        let base = "http://a/b/c/d;p?q";
+        let cases = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("g;x", "http://a/b/c/g;x"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("../../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+        ];
+
+        for (reference, expected) in cases {
+            assert_eq!(resolve(base, reference), expected, "{}", reference);
+        }
+    }
+
+    #[test]
+    fn page_urls() {
+        let base = "https://example.com/blog/post.html";
+        let cases = [
+            ("../css/site.css", "https://example.com/css/site.css"),
+            ("img/a.png", "https://example.com/blog/img/a.png"),
+            ("//cdn.example.com/x", "https://cdn.example.com/x"),
+            ("?page=2", "https://example.com/blog/post.html?page=2"),
+            ("#top", "https://example.com/blog/post.html#top"),
+            ("", "https://example.com/blog/post.html"),
+            ("  a.png ", "https://example.com/blog/a.png"),
+            ("../../../../a", "https://example.com/a"),
+            ("/docs/", "https://example.com/docs/"),
+            ("mailto:a@example.com", "mailto:a@example.com"),
+            ("HTTPS://Other.example/X", "HTTPS://Other.example/X"),
+        ];
+
+        for (reference, expected) in cases {
+            assert_eq!(resolve(base, reference), expected, "{:?}", reference);
+        }
+        // A base without a path.
+        assert_eq!(
+            resolve("https://example.com", "a.png"),
+            "https://example.com/a.png"
+        );
+    }
+}